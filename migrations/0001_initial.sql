@@ -0,0 +1,33 @@
+-- SPDX-FileCopyrightText: 2023 Konrad Borowski <konrad@borowski.pw>
+--
+-- SPDX-License-Identifier: AGPL-3.0-or-later
+
+CREATE TABLE guild_settings (
+    guild_id INTEGER PRIMARY KEY,
+    translator TEXT
+);
+
+CREATE TABLE auto_translate (
+    channel_id INTEGER PRIMARY KEY,
+    mode TEXT NOT NULL,
+    skip TEXT NOT NULL
+);
+
+CREATE TABLE command_usage (
+    command TEXT PRIMARY KEY,
+    count INTEGER NOT NULL
+);
+
+CREATE TABLE snippets (
+    guild_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    author_id INTEGER NOT NULL,
+    content TEXT NOT NULL,
+    PRIMARY KEY (guild_id, name)
+);
+
+CREATE TABLE cache (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL,
+    expires_at INTEGER NOT NULL
+);
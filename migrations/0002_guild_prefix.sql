@@ -0,0 +1,5 @@
+-- SPDX-FileCopyrightText: 2023 Konrad Borowski <konrad@borowski.pw>
+--
+-- SPDX-License-Identifier: AGPL-3.0-or-later
+
+ALTER TABLE guild_settings ADD COLUMN prefix TEXT;
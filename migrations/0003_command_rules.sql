@@ -0,0 +1,12 @@
+-- SPDX-FileCopyrightText: 2023 Konrad Borowski <konrad@borowski.pw>
+--
+-- SPDX-License-Identifier: AGPL-3.0-or-later
+
+-- Rules with channel_id 0 apply to the entire guild
+CREATE TABLE command_rules (
+    guild_id INTEGER NOT NULL,
+    channel_id INTEGER NOT NULL,
+    command TEXT NOT NULL,
+    enabled INTEGER NOT NULL,
+    PRIMARY KEY (guild_id, channel_id, command)
+);
@@ -0,0 +1,293 @@
+// SPDX-FileCopyrightText: 2023 Konrad Borowski <konrad@borowski.pw>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::prefix::split_prefix;
+use crate::ratelimit::{Origin, Resource};
+use crate::rules::is_thread;
+use crate::trans::translation_reply;
+use crate::translator::Options;
+use crate::transport::Reply;
+use crate::{Context, Data};
+use anyhow::Result;
+use log::debug;
+use poise::{command, ChoiceParameter, Event};
+use serenity::client::Context as SerenityContext;
+use serenity::model::channel::Message;
+
+/// Where automatic translations are posted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ChoiceParameter)]
+pub enum Mode {
+    #[name = "reply"]
+    Reply,
+    #[name = "thread"]
+    Thread,
+}
+
+/// Automatic translation settings of a channel.
+#[derive(Clone, Debug)]
+pub struct AutoTranslate {
+    pub mode: Mode,
+    /// Languages whose messages aren't translated.
+    pub skip: Vec<String>,
+}
+
+/// Shorter messages, like greetings or reactions, aren't worth translating.
+const MIN_LENGTH: usize = 10;
+/// Longer messages are skipped to avoid spending translation quota on them.
+const MAX_LENGTH: usize = 2000;
+
+fn should_translate(content: &str) -> bool {
+    let length = content.trim().chars().count();
+    (MIN_LENGTH..=MAX_LENGTH).contains(&length)
+}
+
+impl AutoTranslate {
+    fn should_post(
+        &self,
+        detected: Option<&str>,
+        target: &str,
+        original: &str,
+        translated: &str,
+    ) -> bool {
+        let skipped = detected.is_some_and(|detected| {
+            same_language(target, detected)
+                || self
+                    .skip
+                    .iter()
+                    .any(|language| same_language(language, detected))
+        });
+        !skipped && original.trim() != translated.trim()
+    }
+}
+
+/// Compares language codes ignoring regional variants, so that `EN-US` matches `en`.
+fn same_language(a: &str, b: &str) -> bool {
+    let primary = |code: &str| code.split('-').next().unwrap_or(code).to_ascii_uppercase();
+    primary(a) == primary(b)
+}
+
+/// Show automatic translation settings of this channel.
+///
+/// When enabled, messages in this channel that aren't in one of the \
+/// skipped languages are translated to English automatically. In thread \
+/// mode, messages already in a thread are replied to instead.
+///
+/// Examples:
+/// `!xb autotranslate on`
+/// `!xb autotranslate on thread en pl`
+/// `!xb autotranslate off`
+#[command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_CHANNELS",
+    subcommands("autotranslate_on", "autotranslate_off")
+)]
+pub async fn autotranslate(ctx: Context<'_>) -> Result<()> {
+    let settings = ctx.data().storage.auto_translate(ctx.channel_id().0)?;
+    let message = match settings {
+        Some(AutoTranslate { mode, skip }) => format!(
+            "Automatic translation is enabled ({mode}), skipping {}.",
+            skip.join(", "),
+        ),
+        None => "Automatic translation is disabled.".into(),
+    };
+    ctx.say(message).await?;
+    Ok(())
+}
+
+/// Enable automatic translation in this channel.
+#[command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_CHANNELS",
+    rename = "on"
+)]
+async fn autotranslate_on(
+    ctx: Context<'_>,
+    #[description = "Post translations as replies or in threads"] mode: Option<Mode>,
+    #[description = "Languages not to translate, English when not provided"]
+    #[rest]
+    skip: Option<String>,
+) -> Result<()> {
+    let mut skip: Vec<String> = skip
+        .as_deref()
+        .unwrap_or("")
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|language| !language.is_empty())
+        .map(str::to_ascii_uppercase)
+        .collect();
+    if skip.is_empty() {
+        skip.push("EN".into());
+    }
+    let settings = AutoTranslate {
+        mode: mode.unwrap_or(Mode::Reply),
+        skip,
+    };
+    let message = format!(
+        "Enabled automatic translation ({}), skipping {}.",
+        settings.mode,
+        settings.skip.join(", "),
+    );
+    ctx.data()
+        .storage
+        .set_auto_translate(ctx.channel_id().0, Some(&settings))?;
+    ctx.say(message).await?;
+    Ok(())
+}
+
+/// Disable automatic translation in this channel.
+#[command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_CHANNELS",
+    rename = "off"
+)]
+async fn autotranslate_off(ctx: Context<'_>) -> Result<()> {
+    ctx.data()
+        .storage
+        .set_auto_translate(ctx.channel_id().0, None)?;
+    ctx.say("Disabled automatic translation.").await?;
+    Ok(())
+}
+
+/// Translates messages in channels with automatic translation enabled.
+pub async fn handle_event(ctx: &SerenityContext, event: &Event<'_>, data: &Data) -> Result<()> {
+    let Event::Message { new_message } = event else {
+        return Ok(());
+    };
+    if !data.auto_translate {
+        return Ok(());
+    }
+    if new_message.author.bot
+        || !should_translate(&new_message.content)
+        || split_prefix(
+            data,
+            new_message.guild_id.map(|id| id.0),
+            &new_message.content,
+        )?
+        .is_some()
+    {
+        return Ok(());
+    }
+    let Some(settings) = data.storage.auto_translate(new_message.channel_id.0)? else {
+        return Ok(());
+    };
+    let origin = Origin {
+        user: new_message.author.id.0,
+        channel: new_message.channel_id.0,
+        guild: new_message.guild_id.map(|id| id.0),
+    };
+    // Automatic translations are skipped silently instead of telling users to slow down
+    if data
+        .rate_limiter
+        .check(Resource::AutoTranslation, origin)
+        .is_err()
+    {
+        return Ok(());
+    }
+    let guild_translator = match new_message.guild_id {
+        Some(guild_id) => data.storage.guild_translator(guild_id.0)?,
+        None => None,
+    };
+    let Some(translator) = data.translators.for_guild(guild_translator.as_deref()) else {
+        return Ok(());
+    };
+    let target = translator.default_target();
+    // Failures, like exceeded quota, would otherwise be reported for every message
+    let translation = match translator
+        .translate(&new_message.content, None, target, &Options::default())
+        .await
+    {
+        Ok(translation) => translation,
+        Err(e) => {
+            debug!("Automatic translation failed: {e:#}");
+            return Ok(());
+        }
+    };
+    if !settings.should_post(
+        translation.detected_source.as_deref(),
+        target,
+        &new_message.content,
+        &translation.text,
+    ) {
+        return Ok(());
+    }
+    let languages = match translator.languages().await {
+        Ok(languages) => languages,
+        Err(e) => {
+            debug!("Fetching languages for automatic translation failed: {e:#}");
+            return Ok(());
+        }
+    };
+    let reply = translation_reply(translation, None, target, &languages);
+    post(ctx, new_message, settings.mode, reply).await
+}
+
+async fn post(ctx: &SerenityContext, message: &Message, mode: Mode, reply: Reply) -> Result<()> {
+    // Threads cannot be created inside of threads
+    let mode = match mode {
+        Mode::Thread if is_thread(&message.channel_id.to_channel(ctx).await?) => Mode::Reply,
+        mode => mode,
+    };
+    match mode {
+        Mode::Reply => {
+            message
+                .channel_id
+                .send_message(ctx, |m| {
+                    reply
+                        .build_message(m)
+                        .reference_message(message)
+                        .allowed_mentions(|mentions| mentions.empty_parse().replied_user(false))
+                })
+                .await?;
+        }
+        Mode::Thread => {
+            let thread = message
+                .channel_id
+                .create_public_thread(ctx, message.id, |thread| thread.name("Translation"))
+                .await?;
+            thread
+                .id
+                .send_message(ctx, |m| {
+                    reply
+                        .build_message(m)
+                        .allowed_mentions(|mentions| mentions.empty_parse())
+                })
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{should_translate, AutoTranslate, Mode};
+
+    #[test]
+    fn should_post() {
+        let settings = AutoTranslate {
+            mode: Mode::Reply,
+            skip: vec!["EN".into(), "PL".into()],
+        };
+        assert!(settings.should_post(Some("DE"), "EN-US", "Hallo Welt", "Hello world"));
+        assert!(!settings.should_post(Some("EN"), "EN-US", "Hello world", "Hello, world"));
+        assert!(!settings.should_post(Some("pl"), "EN-US", "Cześć", "Hi"));
+        assert!(!settings.should_post(Some("DE"), "EN-US", "Kindergarten", "Kindergarten"));
+        let settings = AutoTranslate {
+            mode: Mode::Reply,
+            skip: vec![],
+        };
+        assert!(!settings.should_post(Some("en"), "EN-US", "Hello world", "Hello, world"));
+    }
+
+    #[test]
+    fn message_length() {
+        assert!(!should_translate("  ok  "));
+        assert!(should_translate("Wie geht es dir?"));
+        assert!(!should_translate(&"a".repeat(2001)));
+    }
+}
@@ -0,0 +1,233 @@
+// SPDX-FileCopyrightText: 2023 Konrad Borowski <konrad@borowski.pw>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::ratelimit::Limits;
+use anyhow::{bail, Context as _, Result};
+use reqwest::Url;
+use serde::Deserialize;
+use std::fs;
+use std::path::PathBuf;
+
+/// Bot configuration, read from `config.toml` or a file given in `XBOT_CONFIG`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub discord_token: String,
+    #[serde(default = "default_database")]
+    pub database: PathBuf,
+    #[serde(default = "default_languages_file")]
+    pub languages_file: PathBuf,
+    #[serde(default)]
+    pub prefixes: Prefixes,
+    pub sandbox: Sandbox,
+    #[serde(default)]
+    pub godbolt: Godbolt,
+    /// DeepL is disabled when not configured.
+    pub deepl: Option<DeepL>,
+    /// LibreTranslate is disabled when not configured.
+    pub libretranslate: Option<LibreTranslate>,
+    #[serde(default)]
+    pub rate_limits: Limits,
+    #[serde(default)]
+    pub features: Features,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Prefixes {
+    pub default: String,
+    #[serde(default)]
+    pub additional: Vec<String>,
+}
+
+impl Default for Prefixes {
+    fn default() -> Self {
+        Self {
+            default: "!xb ".into(),
+            additional: vec![".xb ".into()],
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Sandbox {
+    pub url: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Godbolt {
+    pub url: String,
+}
+
+impl Default for Godbolt {
+    fn default() -> Self {
+        Self {
+            url: "https://godbolt.org".into(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeepL {
+    pub auth_key: String,
+    #[serde(default = "default_deepl_url")]
+    pub url: String,
+    /// Fraction of the character quota after which translations include a warning.
+    #[serde(default = "default_warn_threshold")]
+    pub warn_threshold: f64,
+    /// Fraction of the character quota after which translations are refused.
+    #[serde(default = "default_refuse_threshold")]
+    pub refuse_threshold: f64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LibreTranslate {
+    pub url: String,
+    pub api_key: Option<String>,
+}
+
+/// Optional features, all enabled by default.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Features {
+    /// Compiler Explorer commands, `asm` and `casm`.
+    pub godbolt: bool,
+    pub auto_translate: bool,
+    pub snippets: bool,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Self {
+            godbolt: true,
+            auto_translate: true,
+            snippets: true,
+        }
+    }
+}
+
+fn default_database() -> PathBuf {
+    "xbot.sqlite3".into()
+}
+
+fn default_languages_file() -> PathBuf {
+    "languages.toml".into()
+}
+
+fn default_deepl_url() -> String {
+    "https://api-free.deepl.com".into()
+}
+
+fn default_warn_threshold() -> f64 {
+    0.9
+}
+
+fn default_refuse_threshold() -> f64 {
+    1.0
+}
+
+impl Config {
+    pub fn load(path: &str) -> Result<Self> {
+        let contents = fs::read_to_string(path).with_context(|| {
+            format!("Cannot read config file {path}, see config.toml.example for an example")
+        })?;
+        let config =
+            Self::parse(&contents).with_context(|| format!("Invalid config file {path}"))?;
+        Ok(config)
+    }
+
+    fn parse(contents: &str) -> Result<Self> {
+        let config: Self = toml::from_str(contents)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.discord_token.trim().is_empty() {
+            bail!("discord_token cannot be empty");
+        }
+        let prefixes = [&self.prefixes.default]
+            .into_iter()
+            .chain(&self.prefixes.additional);
+        for prefix in prefixes {
+            if prefix.trim().is_empty() {
+                bail!("Prefixes cannot be empty");
+            }
+        }
+        let mut urls = vec![
+            ("sandbox.url", &self.sandbox.url),
+            ("godbolt.url", &self.godbolt.url),
+        ];
+        if let Some(deepl) = &self.deepl {
+            urls.push(("deepl.url", &deepl.url));
+            if !(0.0..=1.0).contains(&deepl.warn_threshold) {
+                bail!("deepl.warn_threshold must be between 0 and 1");
+            }
+            if deepl.refuse_threshold < deepl.warn_threshold {
+                bail!("deepl.refuse_threshold cannot be lower than deepl.warn_threshold");
+            }
+        }
+        if let Some(libretranslate) = &self.libretranslate {
+            urls.push(("libretranslate.url", &libretranslate.url));
+        }
+        for (name, url) in urls {
+            Url::parse(url).with_context(|| format!("{name} is not a valid URL: {url}"))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Config;
+
+    #[test]
+    fn example_config_is_valid() {
+        let config = Config::parse(include_str!("../config.toml.example")).unwrap();
+        assert!(config.deepl.is_some());
+        assert!(config.features.godbolt);
+    }
+
+    #[test]
+    fn minimal_config() {
+        let config = Config::parse(
+            r#"
+            discord_token = "token"
+            [sandbox]
+            url = "http://localhost:8080"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.prefixes.default, "!xb ");
+        assert!(config.deepl.is_none());
+    }
+
+    #[test]
+    fn helpful_errors() {
+        let error = |contents| format!("{:#}", Config::parse(contents).err().unwrap());
+        assert!(error("discord_token = 1").contains("invalid type: integer"));
+        assert!(error(
+            r#"
+            discord_token = "token"
+            [sandbox]
+            url = "localhost"
+            "#,
+        )
+        .starts_with("sandbox.url is not a valid URL: localhost"));
+        assert!(error(
+            r#"
+            discord_token = "token"
+            [sandbox]
+            url = "http://localhost:8080"
+            [rate_limits]
+            user = "5"
+            "#,
+        )
+        .contains("Expected quota in requests/seconds format, got 5"));
+    }
+}
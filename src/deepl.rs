@@ -0,0 +1,691 @@
+// SPDX-FileCopyrightText: 2022 - 2023 Konrad Borowski <konrad@borowski.pw>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::translator::{Language, Languages, Options, Translation, Translator};
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use log::warn;
+use reqwest::multipart::{Form, Part};
+use reqwest::{Client, Response};
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+
+/// Languages used when DeepL's language list cannot be fetched.
+const SOURCE_LANGUAGES: &[&str] = &[
+    "BG", "CS", "DA", "DE", "EL", "EN", "ES", "ET", "FI", "FR", "HU", "ID", "IT", "JA", "LT", "LV",
+    "NL", "PL", "PT", "RO", "SK", "SL", "SV", "TR", "UK", "ZH",
+];
+const TARGET_LANGUAGES: &[&str] = &[
+    "BG", "CS", "DA", "DE", "EL", "EN", "EN-GB", "EN-US", "ES", "ET", "FI", "FR", "HU", "ID", "IT",
+    "JA", "LT", "LV", "NL", "PL", "PT", "PT-BR", "PT-PT", "RO", "RU", "SK", "SL", "SV", "TR", "UK",
+    "ZH",
+];
+
+/// How long fetched DeepL languages are trusted before fetching them again.
+const LANGUAGES_REFRESH_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// DeepL bills every document as having at least this many characters.
+const MINIMUM_DOCUMENT_CHARACTERS: u64 = 50_000;
+
+/// How many times document translation status is checked before giving up.
+const DOCUMENT_POLL_ATTEMPTS: u32 = 60;
+
+/// How long fetched DeepL usage is trusted before fetching it again.
+const USAGE_REFRESH_INTERVAL: Duration = Duration::from_secs(600);
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct Usage {
+    pub character_count: u64,
+    pub character_limit: u64,
+}
+
+impl Usage {
+    pub fn used_fraction(self, additional_characters: u64) -> f64 {
+        (self.character_count + additional_characters) as f64 / self.character_limit.max(1) as f64
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum QuotaState {
+    Available,
+    Warning,
+    Exhausted,
+}
+
+/// Tracks characters translated with DeepL to avoid running into its quota.
+pub struct UsageTracker {
+    warn_threshold: f64,
+    refuse_threshold: f64,
+    cached: Mutex<Option<(Usage, Instant)>>,
+}
+
+impl UsageTracker {
+    pub fn new(warn_threshold: f64, refuse_threshold: f64) -> Self {
+        Self {
+            warn_threshold,
+            refuse_threshold,
+            cached: Mutex::new(None),
+        }
+    }
+
+    fn state(&self, usage: Usage, characters: u64) -> QuotaState {
+        let used = usage.used_fraction(characters);
+        if used > self.refuse_threshold {
+            QuotaState::Exhausted
+        } else if used >= self.warn_threshold {
+            QuotaState::Warning
+        } else {
+            QuotaState::Available
+        }
+    }
+
+    fn record(&self, characters: u64) {
+        if let Some((usage, _)) = &mut *self.cached.lock().unwrap() {
+            usage.character_count += characters;
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct DeepLLanguage {
+    language: String,
+    name: String,
+}
+
+#[derive(Serialize)]
+struct TranslateRequest<'a> {
+    text: &'a str,
+    source_lang: Option<&'a str>,
+    target_lang: &'a str,
+    formality: Option<&'a str>,
+    glossary_id: Option<&'a str>,
+    preserve_formatting: Option<&'a str>,
+    tag_handling: Option<&'a str>,
+    context: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct TranslateResponse {
+    translations: [DeepLTranslation; 1],
+}
+
+#[derive(Deserialize)]
+struct DeepLTranslation {
+    text: String,
+    detected_source_language: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    message: String,
+}
+
+#[derive(Serialize)]
+struct CreateGlossary<'a> {
+    name: &'a str,
+    source_lang: &'a str,
+    target_lang: &'a str,
+    entries: &'a str,
+    entries_format: &'a str,
+}
+
+#[derive(Deserialize)]
+struct DocumentHandle {
+    document_id: String,
+    document_key: String,
+}
+
+#[derive(Serialize)]
+struct DocumentKey<'a> {
+    document_key: &'a str,
+}
+
+#[derive(Deserialize)]
+struct DocumentStatus {
+    status: String,
+    seconds_remaining: Option<u64>,
+    error_message: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct Glossary {
+    pub glossary_id: String,
+    pub name: String,
+    pub source_lang: String,
+    pub target_lang: String,
+    pub entry_count: u64,
+}
+
+#[derive(Deserialize)]
+struct Glossaries {
+    glossaries: Vec<Glossary>,
+}
+
+/// Turns DeepL error responses into errors containing DeepL's explanation.
+async fn check_status(response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    match response.json().await {
+        Ok(ErrorResponse { message }) => bail!("DeepL error: {message}"),
+        Err(_) => bail!("DeepL responded with {status}"),
+    }
+}
+
+pub struct DeepL {
+    client: Client,
+    url: String,
+    auth_key: String,
+    usage: UsageTracker,
+    languages: Mutex<Option<(Languages, Instant)>>,
+}
+
+impl DeepL {
+    pub fn new(client: Client, url: String, auth_key: String, usage: UsageTracker) -> Self {
+        Self {
+            client,
+            url,
+            auth_key,
+            usage,
+            languages: Mutex::new(None),
+        }
+    }
+
+    /// Creates a glossary from `entries` in `source,target` CSV format.
+    pub async fn create_glossary(
+        &self,
+        name: &str,
+        source: &str,
+        target: &str,
+        entries: &str,
+    ) -> Result<Glossary> {
+        let response = self
+            .client
+            .post(format!("{}/v2/glossaries", self.url))
+            .header("Authorization", format!("DeepL-Auth-Key {}", self.auth_key))
+            .form(&CreateGlossary {
+                name,
+                source_lang: source,
+                target_lang: target,
+                entries,
+                entries_format: "csv",
+            })
+            .send()
+            .await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    pub async fn glossaries(&self) -> Result<Vec<Glossary>> {
+        let response = self
+            .client
+            .get(format!("{}/v2/glossaries", self.url))
+            .header("Authorization", format!("DeepL-Auth-Key {}", self.auth_key))
+            .send()
+            .await?;
+        let Glossaries { glossaries } = check_status(response).await?.json().await?;
+        Ok(glossaries)
+    }
+
+    /// Refuses translations that would go over the quota. When usage can't be
+    /// checked translations are allowed, DeepL still enforces the quota.
+    async fn reserve(&self, characters: u64) -> Result<Quota> {
+        let usage = match self.cached_usage().await {
+            Ok(usage) => usage,
+            Err(e) => {
+                warn!("Cannot check DeepL usage, translating without quota check: {e:#}");
+                return Ok(Quota {
+                    usage: None,
+                    state: QuotaState::Available,
+                });
+            }
+        };
+        let state = self.usage.state(usage, characters);
+        if state == QuotaState::Exhausted {
+            bail!(
+                "DeepL character quota is exhausted, translations are unavailable until it resets."
+            );
+        }
+        Ok(Quota {
+            usage: Some(usage),
+            state,
+        })
+    }
+
+    async fn fetch_languages(&self, kind: &str) -> Result<Vec<Language>> {
+        let languages: Vec<DeepLLanguage> = self
+            .client
+            .get(format!("{}/v2/languages", self.url))
+            .query(&[("type", kind)])
+            .header("Authorization", format!("DeepL-Auth-Key {}", self.auth_key))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(languages
+            .into_iter()
+            .map(|language| Language {
+                code: language.language,
+                name: language.name,
+            })
+            .collect())
+    }
+
+    /// Fetches supported languages, keeping the previous list (or the built-in
+    /// one) when DeepL cannot be reached.
+    pub async fn refresh_languages(&self) -> Languages {
+        let fetched = async {
+            anyhow::Ok(Languages {
+                source: self.fetch_languages("source").await?,
+                target: self.fetch_languages("target").await?,
+            })
+        };
+        let languages = match fetched.await {
+            Ok(languages) => languages,
+            Err(e) => {
+                warn!("Cannot fetch DeepL languages: {e}");
+                let previous = self.languages.lock().unwrap().take();
+                previous.map_or_else(fallback_languages, |(languages, _)| languages)
+            }
+        };
+        *self.languages.lock().unwrap() = Some((languages.clone(), Instant::now()));
+        languages
+    }
+
+    pub async fn usage(&self) -> Result<Usage> {
+        let response = self
+            .client
+            .get(format!("{}/v2/usage", self.url))
+            .header("Authorization", format!("DeepL-Auth-Key {}", self.auth_key))
+            .send()
+            .await?;
+        let usage: Usage = check_status(response).await?.json().await?;
+        *self.usage.cached.lock().unwrap() = Some((usage, Instant::now()));
+        Ok(usage)
+    }
+
+    async fn cached_usage(&self) -> Result<Usage> {
+        let cached = *self.usage.cached.lock().unwrap();
+        match cached {
+            Some((usage, fetched)) if fetched.elapsed() < USAGE_REFRESH_INTERVAL => Ok(usage),
+            _ => self.usage().await,
+        }
+    }
+}
+
+#[async_trait]
+impl Translator for DeepL {
+    fn name(&self) -> &'static str {
+        "deepl"
+    }
+
+    fn default_target(&self) -> &'static str {
+        "EN-US"
+    }
+
+    async fn languages(&self) -> Result<Languages> {
+        let cached = self.languages.lock().unwrap().clone();
+        Ok(match cached {
+            Some((languages, fetched)) if fetched.elapsed() < LANGUAGES_REFRESH_INTERVAL => {
+                languages
+            }
+            _ => self.refresh_languages().await,
+        })
+    }
+
+    async fn translate(
+        &self,
+        text: &str,
+        source: Option<&str>,
+        target: &str,
+        options: &Options,
+    ) -> Result<Translation> {
+        if options.glossary_id.is_some() && source.is_none() {
+            bail!("Using a glossary requires specifying the source language.");
+        }
+        let characters = text.chars().count() as u64;
+        let quota = self.reserve(characters).await?;
+        let response = self
+            .client
+            .post(format!("{}/v2/translate", self.url))
+            .header("Authorization", format!("DeepL-Auth-Key {}", self.auth_key))
+            .form(&TranslateRequest {
+                text,
+                source_lang: source,
+                target_lang: target,
+                formality: options.formality.map(|formality| formality.name()),
+                glossary_id: options.glossary_id.as_deref(),
+                preserve_formatting: options.preserve_formatting.then_some("1"),
+                tag_handling: options.tag_handling.map(|tag_handling| tag_handling.name()),
+                context: options.context.as_deref(),
+            })
+            .send()
+            .await?;
+        let response: TranslateResponse = check_status(response).await?.json().await?;
+        self.usage.record(characters);
+        let [DeepLTranslation {
+            text,
+            detected_source_language,
+        }] = response.translations;
+        Ok(Translation {
+            text,
+            detected_source: detected_source_language,
+            notice: quota.notice(characters),
+        })
+    }
+
+    async fn translate_document(
+        &self,
+        name: &str,
+        contents: String,
+        source: Option<&str>,
+        target: &str,
+        options: &Options,
+    ) -> Result<Translation> {
+        if options.preserve_formatting
+            || options.tag_handling.is_some()
+            || options.context.is_some()
+        {
+            bail!("Documents cannot be translated with preserve_formatting, tag_handling or context options.");
+        }
+        if options.glossary_id.is_some() && source.is_none() {
+            bail!("Using a glossary requires specifying the source language.");
+        }
+        let characters = (contents.chars().count() as u64).max(MINIMUM_DOCUMENT_CHARACTERS);
+        let quota = self.reserve(characters).await?;
+        let mut form = Form::new()
+            .text("target_lang", target.to_string())
+            .part("file", Part::text(contents).file_name(name.to_string()));
+        for (key, value) in [
+            ("source_lang", source),
+            (
+                "formality",
+                options.formality.map(|formality| formality.name()),
+            ),
+            ("glossary_id", options.glossary_id.as_deref()),
+        ] {
+            if let Some(value) = value {
+                form = form.text(key, value.to_string());
+            }
+        }
+        let response = self
+            .client
+            .post(format!("{}/v2/document", self.url))
+            .header("Authorization", format!("DeepL-Auth-Key {}", self.auth_key))
+            .multipart(form)
+            .send()
+            .await?;
+        let document: DocumentHandle = check_status(response).await?.json().await?;
+        self.usage.record(characters);
+        let url = format!("{}/v2/document/{}", self.url, document.document_id);
+        let key = DocumentKey {
+            document_key: &document.document_key,
+        };
+        for _ in 0..DOCUMENT_POLL_ATTEMPTS {
+            let response = self
+                .client
+                .post(&url)
+                .header("Authorization", format!("DeepL-Auth-Key {}", self.auth_key))
+                .form(&key)
+                .send()
+                .await?;
+            let status: DocumentStatus = check_status(response).await?.json().await?;
+            match status.status.as_str() {
+                "done" => {
+                    let response = self
+                        .client
+                        .post(format!("{url}/result"))
+                        .header("Authorization", format!("DeepL-Auth-Key {}", self.auth_key))
+                        .form(&key)
+                        .send()
+                        .await?;
+                    return Ok(Translation {
+                        text: check_status(response).await?.text().await?,
+                        detected_source: None,
+                        notice: quota.notice(characters),
+                    });
+                }
+                "error" => bail!(
+                    "DeepL error: {}",
+                    status.error_message.as_deref().unwrap_or("unknown error")
+                ),
+                _ => {
+                    let wait = status.seconds_remaining.unwrap_or(1).clamp(1, 10);
+                    sleep(Duration::from_secs(wait)).await;
+                }
+            }
+        }
+        bail!("Translating {name} took too long.")
+    }
+}
+
+/// Quota state at the time a translation was requested.
+struct Quota {
+    /// `None` when usage couldn't be checked.
+    usage: Option<Usage>,
+    state: QuotaState,
+}
+
+impl Quota {
+    fn notice(&self, characters: u64) -> Option<String> {
+        let usage = self.usage?;
+        (self.state == QuotaState::Warning).then(|| {
+            let percentage = usage.used_fraction(characters) * 100.0;
+            warn!("{percentage:.1}% of DeepL character quota used");
+            format!("{percentage:.0}% of DeepL character quota used.")
+        })
+    }
+}
+
+fn fallback_languages() -> Languages {
+    let to_vec = |list: &[&str]| {
+        list.iter()
+            .map(|&code| Language {
+                code: code.into(),
+                name: code.into(),
+            })
+            .collect()
+    };
+    Languages {
+        source: to_vec(SOURCE_LANGUAGES),
+        target: to_vec(TARGET_LANGUAGES),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DeepL, QuotaState, Usage, UsageTracker};
+    use crate::translator::{Formality, Language, Options, Translator};
+    use reqwest::Client;
+    use serde_json::json;
+    use wiremock::matchers::{body_string_contains, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn deepl(url: String) -> DeepL {
+        DeepL::new(
+            Client::new(),
+            url,
+            String::new(),
+            UsageTracker::new(0.9, 1.0),
+        )
+    }
+
+    #[test]
+    fn quota_state() {
+        let tracker = UsageTracker::new(0.9, 0.99);
+        let usage = Usage {
+            character_count: 850,
+            character_limit: 1000,
+        };
+        assert_eq!(tracker.state(usage, 10), QuotaState::Available);
+        assert_eq!(tracker.state(usage, 50), QuotaState::Warning);
+        assert_eq!(tracker.state(usage, 141), QuotaState::Exhausted);
+    }
+
+    #[tokio::test]
+    async fn languages() {
+        let server = MockServer::start().await;
+        for (kind, body) in [
+            ("source", json!([{ "language": "KO", "name": "Korean" }])),
+            (
+                "target",
+                json!([{ "language": "NB", "name": "Norwegian", "supports_formality": false }]),
+            ),
+        ] {
+            Mock::given(method("GET"))
+                .and(path("/v2/languages"))
+                .and(query_param("type", kind))
+                .respond_with(ResponseTemplate::new(200).set_body_json(body))
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+        let deepl = deepl(server.uri());
+        for _ in 0..2 {
+            let languages = deepl.languages().await.unwrap();
+            let korean = Language {
+                code: "KO".into(),
+                name: "Korean".into(),
+            };
+            assert_eq!(languages.source, [korean]);
+            assert_eq!(languages.target[0].name, "Norwegian");
+        }
+    }
+
+    #[tokio::test]
+    async fn fallback_languages() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&server)
+            .await;
+        let languages = deepl(server.uri()).languages().await.unwrap();
+        assert!(languages
+            .target
+            .iter()
+            .any(|language| language.code == "EN-US"));
+    }
+
+    #[tokio::test]
+    async fn usage_failure() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v2/usage"))
+            .respond_with(ResponseTemplate::new(403).set_body_json(json!({
+                "message": "Wrong endpoint",
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/v2/translate"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "translations": [{ "text": "Hallo", "detected_source_language": "EN" }],
+            })))
+            .mount(&server)
+            .await;
+        let deepl = deepl(server.uri());
+        let error = deepl.usage().await.err().unwrap();
+        assert_eq!(error.to_string(), "DeepL error: Wrong endpoint");
+        let translation = deepl
+            .translate("Hello", None, "DE", &Options::default())
+            .await
+            .unwrap();
+        assert_eq!(translation.text, "Hallo");
+        assert_eq!(translation.notice, None);
+    }
+
+    #[tokio::test]
+    async fn translate_options() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v2/usage"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "character_count": 0,
+                "character_limit": 500000,
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/v2/translate"))
+            .and(body_string_contains("formality=prefer_less"))
+            .and(body_string_contains("glossary_id=abc"))
+            .and(body_string_contains("preserve_formatting=1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "translations": [{ "text": "Hallo", "detected_source_language": "EN" }],
+            })))
+            .mount(&server)
+            .await;
+        let options = Options {
+            formality: Some(Formality::PreferLess),
+            glossary_id: Some("abc".into()),
+            preserve_formatting: true,
+            ..Options::default()
+        };
+        let deepl = deepl(server.uri());
+        let translation = deepl
+            .translate("Hello", Some("EN"), "DE", &options)
+            .await
+            .unwrap();
+        assert_eq!(translation.text, "Hallo");
+        let error = deepl
+            .translate("Hello", None, "DE", &options)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Using a glossary requires specifying the source language.",
+        );
+    }
+
+    #[tokio::test]
+    async fn translate_document() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v2/usage"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "character_count": 0,
+                "character_limit": 500000,
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/v2/document"))
+            .and(body_string_contains("Hallo Welt"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "document_id": "ID",
+                "document_key": "KEY",
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/v2/document/ID"))
+            .and(body_string_contains("document_key=KEY"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "document_id": "ID",
+                "status": "done",
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/v2/document/ID/result"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("Hello world"))
+            .mount(&server)
+            .await;
+        let translation = deepl(server.uri())
+            .translate_document(
+                "a.txt",
+                "Hallo Welt".into(),
+                None,
+                "EN-US",
+                &Options::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(translation.text, "Hello world");
+    }
+}
@@ -2,65 +2,260 @@
 //
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
-use crate::Context;
-use anyhow::Result;
+use crate::library;
+use crate::ratelimit::{self, Resource};
+use crate::toolchain::{Allowlist, EvalOptions, CPP_OPTIONS, PYTHON_OPTIONS, RUST_OPTIONS};
+use crate::transport::{Attachment, Reply, Transport};
+use crate::{ApplicationContext, Context, Data};
+use anyhow::{bail, Error, Result};
 use once_cell::sync::Lazy;
-use poise::command;
+use poise::{command, Modal};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use serenity::model::channel::AttachmentType;
 use serenity::utils::MessageBuilder;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 
 #[derive(Serialize)]
 struct Command<'a, F> {
     stdin: &'a str,
     code: &'a str,
+    /// Compilation part of `code`, for sandboxes reporting phases separately.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compile: Option<&'a str>,
+    /// Part of `code` running the program, for sandboxes reporting phases separately.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    run: Option<&'a str>,
     files: F,
 }
 
-#[derive(Serialize)]
-struct Files {
-    code: File,
-}
-
 #[derive(Serialize)]
 struct NoFiles {}
 
 #[derive(Serialize)]
-struct File {
-    contents: String,
+struct File<'a> {
+    contents: &'a str,
 }
 
 #[derive(Deserialize)]
 struct Response {
+    #[serde(default)]
     output: String,
     status: Option<i32>,
+    /// Provided by sandboxes reporting compilation and running separately.
+    #[serde(default)]
+    phases: Vec<Phase>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum PhaseName {
+    Compile,
+    Run,
+}
+
+#[derive(Debug, Deserialize)]
+struct Phase {
+    name: PhaseName,
+    #[serde(default)]
+    stdout: String,
+    #[serde(default)]
+    stderr: String,
+    /// Exit status, missing when the process was killed.
+    status: Option<i32>,
+    /// Signal which terminated the process.
+    signal: Option<i32>,
+    /// Wall time in seconds.
+    time: Option<f64>,
+    /// Peak memory use in bytes.
+    memory: Option<u64>,
+}
+
+impl Phase {
+    fn succeeded(&self) -> bool {
+        self.status == Some(0)
+    }
+
+    fn output(&self) -> Output {
+        let mut output = Output::default();
+        output.push(Stream::Stdout, &FILTER.replace_all(&self.stdout, ""));
+        output.push(Stream::Stderr, &FILTER.replace_all(&self.stderr, ""));
+        output
+    }
+
+    fn status_message(&self) -> String {
+        match (self.signal, self.status) {
+            (Some(signal), _) => format!("{}\n", signal_message(signal)),
+            (None, status) => status_message(status).into(),
+        }
+    }
+
+    fn usage(&self) -> Option<String> {
+        let time = self.time.map(|time| format!("{time:.2} s"));
+        let memory = self
+            .memory
+            .map(|memory| format!("{:.1} MiB", memory as f64 / (1024.0 * 1024.0)));
+        let usage: Vec<_> = time.into_iter().chain(memory).collect();
+        (!usage.is_empty()).then(|| format!("Used {}.", usage.join(", ")))
+    }
+}
+
+fn signal_message(signal: i32) -> String {
+    let (description, name) = match signal {
+        1 => ("Hangup", "SIGHUP"),
+        2 => ("Interrupted", "SIGINT"),
+        3 => ("Quit", "SIGQUIT"),
+        4 => ("Illegal instruction", "SIGILL"),
+        5 => ("Trace/breakpoint trap", "SIGTRAP"),
+        6 => ("Aborted", "SIGABRT"),
+        7 => ("Bus error", "SIGBUS"),
+        8 => ("Floating point exception", "SIGFPE"),
+        9 => ("Killed", "SIGKILL"),
+        11 => ("Segmentation fault", "SIGSEGV"),
+        13 => ("Broken pipe", "SIGPIPE"),
+        14 => ("Alarm clock", "SIGALRM"),
+        15 => ("Terminated", "SIGTERM"),
+        24 => ("CPU time limit exceeded", "SIGXCPU"),
+        25 => ("File size limit exceeded", "SIGXFSZ"),
+        _ => return format!("Killed by signal {signal}"),
+    };
+    format!("{description} ({name})")
+}
+
+/// Shell commands evaluating code.
+pub(crate) struct Script {
+    /// Compiles the program, reported as a separate phase when supported by the sandbox.
+    compile: Option<String>,
+    run: String,
+}
+
+impl Script {
+    pub(crate) fn new(run: String) -> Self {
+        Self { compile: None, run }
+    }
+
+    pub(crate) fn compiled(compile: String, run: &str) -> Self {
+        Self {
+            compile: Some(compile),
+            run: run.into(),
+        }
+    }
+
+    /// Complete command, for sandboxes not supporting phases.
+    fn command(&self) -> String {
+        match &self.compile {
+            Some(compile) => format!("{compile} && {}", self.run),
+            None => self.run.clone(),
+        }
+    }
+}
+
+/// Joins command fragments with spaces, skipping empty ones.
+fn command_line(fragments: &[&str]) -> String {
+    let fragments: Vec<_> = fragments
+        .iter()
+        .copied()
+        .filter(|fragment| !fragment.is_empty())
+        .collect();
+    fragments.join(" ")
 }
 
 #[derive(Debug, PartialEq, Eq)]
-struct Parsed<'a> {
-    options: &'a str,
-    code: &'a str,
+pub(crate) struct Parsed<'a> {
+    pub(crate) options: &'a str,
+    pub(crate) code: &'a str,
+    pub(crate) stdin: &'a str,
+    pub(crate) files: Vec<(&'a str, &'a str)>,
 }
 
 impl<'a> Parsed<'a> {
-    fn new(options: &'a str, code: &'a str) -> Parsed<'a> {
+    pub(crate) fn new(options: &'a str, code: &'a str, stdin: &'a str) -> Parsed<'a> {
         Self {
             options: options.trim(),
             code,
+            stdin,
+            files: Vec::new(),
+        }
+    }
+}
+
+enum Label<'a> {
+    Code,
+    Input,
+    File(&'a str),
+}
+
+/// Checks whether a name can be used for an additional file. Names starting
+/// with `-` would be interpreted as options by compiler globs.
+fn is_file_name(word: &str) -> bool {
+    word.contains('.')
+        && !word.starts_with(['.', '-'])
+        && word
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || b"._-".contains(&c))
+}
+
+fn parse_label(label: &str) -> Option<Label<'_>> {
+    fn is_language(word: &str) -> bool {
+        word.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'+')
+    }
+    let mut words = label.split_whitespace();
+    let label = match (words.next(), words.next()) {
+        (None, _) => Label::Code,
+        (Some("input"), None) => Label::Input,
+        (Some(word), None) if is_language(word) => Label::Code,
+        (Some(word), None) if is_file_name(word) => Label::File(word),
+        (Some(language), Some(word)) if is_language(language) && is_file_name(word) => {
+            Label::File(word)
+        }
+        _ => return None,
+    };
+    words.next().is_none().then_some(label)
+}
+
+/// Parses code blocks following the first triple backtick.
+///
+/// Unlabelled block (or one labelled with a language) contains the code,
+/// block labelled `input` contains standard input and blocks labelled
+/// with file names contain additional files.
+fn parse_code_blocks<'a>(options: &'a str, mut rest: &'a str) -> Option<Parsed<'a>> {
+    let mut code = None;
+    let mut stdin = None;
+    let mut files = Vec::new();
+    loop {
+        let (label, after_label) = rest.split_once('\n')?;
+        let (contents, after_block) = after_label.split_once("```")?;
+        match parse_label(label)? {
+            Label::Code if code.is_none() => code = Some(contents),
+            Label::Input if stdin.is_none() => stdin = Some(contents),
+            Label::File(name) => files.push((name, contents)),
+            _ => return None,
+        }
+        rest = after_block.trim_start();
+        if rest.is_empty() {
+            break;
         }
+        rest = rest.strip_prefix("```")?;
     }
+    Some(Parsed {
+        files,
+        ..Parsed::new(options, code?, stdin.unwrap_or(""))
+    })
 }
 
-fn parse_code(mut s: &str) -> Parsed<'_> {
+pub(crate) fn parse_code(mut s: &str) -> Parsed<'_> {
     if let Some((options, without_prefix)) = s.split_once("```") {
+        if let Some(parsed) = parse_code_blocks(options, without_prefix) {
+            return parsed;
+        }
+        // Code containing triple backticks
         if let Some((first_line, rest)) = without_prefix.split_once('\n') {
             if first_line
                 .bytes()
                 .all(|c| c.is_ascii_alphanumeric() || c == b'+')
             {
                 if let Some(code) = rest.strip_suffix("```") {
-                    return Parsed::new(options, code);
+                    return Parsed::new(options, code, "");
                 }
             }
         }
@@ -75,25 +270,101 @@ fn parse_code(mut s: &str) -> Parsed<'_> {
             }
         }
     }
-    Parsed::new(options, s)
+    Parsed::new(options, s, "")
+}
+
+static FILTER: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"/nix/store/[^/]+-gcc-[^/]+/include/c[+][+]/[^/]+/").unwrap());
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Program output split into standard output and standard error segments.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Output {
+    segments: Vec<(Stream, String)>,
 }
 
-static FILTER: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(r"/nix/store/[^/]+-gcc-[^/]+/include/c[+][+]/[^/]+/|\x7F[EO]").unwrap()
-});
+impl Output {
+    /// Parses output with `\x7FO` and `\x7FE` markers switching between
+    /// streams, `\x7F\x7F` stands for a literal `\x7F`.
+    fn parse(output: &str) -> Self {
+        let mut parsed = Self::default();
+        let mut stream = Stream::Stdout;
+        let mut text = String::new();
+        let mut chars = output.chars();
+        while let Some(c) = chars.next() {
+            if c != '\x7F' {
+                text.push(c);
+                continue;
+            }
+            let next = match chars.next() {
+                Some('O') => Stream::Stdout,
+                Some('E') => Stream::Stderr,
+                Some('\x7F') | None => {
+                    text.push('\x7F');
+                    continue;
+                }
+                Some(c) => {
+                    text.push('\x7F');
+                    text.push(c);
+                    continue;
+                }
+            };
+            parsed.push(stream, &text);
+            text.clear();
+            stream = next;
+        }
+        parsed.push(stream, &text);
+        parsed
+    }
+
+    fn push(&mut self, stream: Stream, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        match self.segments.last_mut() {
+            Some((last, last_text)) if *last == stream => last_text.push_str(text),
+            _ => self.segments.push((stream, text.into())),
+        }
+    }
+
+    fn text(&self) -> String {
+        self.segments.iter().map(|(_, text)| &**text).collect()
+    }
+
+    /// Formats output for `ansi` code block, with standard error in red.
+    fn ansi(&self) -> String {
+        self.segments
+            .iter()
+            .map(|(stream, text)| match stream {
+                Stream::Stdout => text.clone(),
+                Stream::Stderr => format!("\x1B[31m{text}\x1B[0m"),
+            })
+            .collect()
+    }
+
+    fn has_stderr(&self) -> bool {
+        self.segments
+            .iter()
+            .any(|(stream, _)| *stream == Stream::Stderr)
+    }
+}
 
 fn more_than_15_newlines(s: &str) -> bool {
     s.bytes().filter(|&c| c == b'\n').nth(15 - 1).is_some()
 }
 
-async fn sandbox_request<F>(ctx: Context<'_>, command: &Command<'_, F>) -> Result<Response>
+async fn sandbox_request<F>(data: &Data, command: &Command<'_, F>) -> Result<Response>
 where
     F: Serialize,
 {
-    Ok(ctx
-        .data()
+    Ok(data
         .client
-        .post(&ctx.data().sandbox_url)
+        .post(&data.sandbox_url)
         .json(command)
         .send()
         .await?
@@ -101,60 +372,188 @@ where
         .await?)
 }
 
-async fn eval(
-    ctx: Context<'_>,
-    code: &str,
+pub(crate) async fn eval(
+    ctx: &impl Transport,
+    Parsed {
+        options,
+        code,
+        stdin,
+        files,
+    }: Parsed<'_>,
+    file_name: &str,
     int_main: &str,
     int_main_wrapper: impl FnOnce(&str) -> String,
-    runner: impl FnOnce(&str) -> String,
+    allowlist: Allowlist<'_>,
+    runner: impl FnOnce(&EvalOptions, &[&str]) -> Script,
 ) -> Result<()> {
-    let Parsed { options, code } = parse_code(code);
+    let options = allowlist.parse(options)?;
     let code = if code.contains(int_main) {
         code.to_string()
     } else {
         int_main_wrapper(code.trim())
     };
-    let Response { output, status } = sandbox_request(
-        ctx,
+    let attachments = ctx.attached_files(|_| true).await?;
+    let mut all_files = BTreeMap::new();
+    let additional_files = files.into_iter().chain(
+        attachments
+            .iter()
+            .map(|(name, contents)| (&**name, &**contents)),
+    );
+    let stem = |name: &str| name.split('.').next().unwrap_or(name).to_string();
+    for (name, contents) in additional_files {
+        if !is_file_name(name) {
+            bail!("File name {name} is not allowed");
+        }
+        if stem(name) == stem(file_name) {
+            bail!("File name {name} is reserved for the evaluated code");
+        }
+        if all_files.insert(name, File { contents }).is_some() {
+            bail!("File {name} is provided multiple times");
+        }
+    }
+    all_files.insert(file_name, File { contents: &code });
+    let sources: Vec<_> = all_files.values().map(|file| file.contents).collect();
+    let script = runner(&options, &sources);
+    // Checked last, so that rejected input doesn't use up the quota
+    if !ratelimit::allow(ctx, Resource::Sandbox).await? {
+        return Ok(());
+    }
+    let Response {
+        output,
+        status,
+        phases,
+    } = sandbox_request(
+        ctx.data(),
         &Command {
-            stdin: "",
-            code: &runner(options),
-            files: Files {
-                code: File { contents: code },
-            },
+            stdin,
+            code: &script.command(),
+            compile: script.compile.as_deref(),
+            run: Some(&script.run),
+            files: all_files,
         },
     )
     .await?;
-    let output = FILTER.replace_all(&output, "").replace("\x7F\x7F", "\x7F");
-    post_output(ctx, &output, status).await
+    if !phases.is_empty() {
+        return post_phases(ctx, &phases).await;
+    }
+    let output = Output::parse(&FILTER.replace_all(&output, ""));
+    post(ctx, &status_message(status), &output, None).await
 }
 
-async fn post_output(ctx: Context<'_>, output: &str, status: Option<i32>) -> Result<()> {
-    let formatted;
-    let status_message = match status {
-        Some(0) => "",
-        Some(status) => {
-            formatted = format!("Exited with status code {status}\n");
-            &formatted
+/// Code provided by slash command users.
+#[derive(Modal)]
+#[name = "Evaluate code"]
+pub(crate) struct CodeModal {
+    #[name = "Code"]
+    #[paragraph]
+    code: String,
+    #[name = "Options"]
+    #[placeholder = "Compiler or interpreter options"]
+    options: Option<String>,
+    #[name = "Input"]
+    #[placeholder = "Standard input"]
+    #[paragraph]
+    stdin: Option<String>,
+}
+
+impl CodeModal {
+    pub(crate) fn parsed(&self) -> Parsed<'_> {
+        Parsed::new(
+            self.options.as_deref().unwrap_or(""),
+            &self.code,
+            self.stdin.as_deref().unwrap_or(""),
+        )
+    }
+}
+
+/// Uses the slash command of `modal` for `prefix`.
+///
+/// Code blocks are awkward to type in slash command options, so the slash
+/// commands ask for the code with a modal instead.
+pub(crate) fn with_modal(
+    prefix: poise::Command<Data, Error>,
+    modal: poise::Command<Data, Error>,
+) -> poise::Command<Data, Error> {
+    poise::Command {
+        slash_action: modal.slash_action,
+        parameters: modal.parameters,
+        ..prefix
+    }
+}
+
+fn status_message(status: Option<i32>) -> Cow<'static, str> {
+    match status {
+        Some(0) => "".into(),
+        Some(status) => format!("Exited with status code {status}\n").into(),
+        None => "Killed the process due to timeout\n".into(),
+    }
+}
+
+pub(crate) async fn post_output(
+    ctx: &impl Transport,
+    output: &str,
+    status: Option<i32>,
+) -> Result<()> {
+    let mut parsed = Output::default();
+    parsed.push(Stream::Stdout, output);
+    post(ctx, &status_message(status), &parsed, None).await
+}
+
+/// Posts output of the failed compilation, or of the program otherwise.
+async fn post_phases(ctx: &impl Transport, phases: &[Phase]) -> Result<()> {
+    let failed_compilation = phases
+        .iter()
+        .find(|phase| phase.name == PhaseName::Compile && !phase.succeeded());
+    if let Some(compile) = failed_compilation {
+        let message = format!("Compilation failed\n{}", compile.status_message());
+        return post(ctx, &message, &compile.output(), None).await;
+    }
+    // Compiler warnings are shown before the program output
+    let mut output = Output::default();
+    for phase in phases {
+        for (stream, text) in phase.output().segments {
+            output.push(stream, &text);
         }
-        None => "Killed the process due to timeout\n",
-    };
-    if output.len() > 800 || more_than_15_newlines(output) {
-        ctx.send(|m| {
-            m.attachment(AttachmentType::Bytes {
-                data: output.as_bytes().into(),
+    }
+    let run = phases.iter().find(|phase| phase.name == PhaseName::Run);
+    let status_message = run.map_or_else(String::new, Phase::status_message);
+    let usage = run.and_then(Phase::usage);
+    post(ctx, &status_message, &output, usage.as_deref()).await
+}
+
+async fn post(
+    ctx: &impl Transport,
+    status_message: &str,
+    output: &Output,
+    usage: Option<&str>,
+) -> Result<()> {
+    let text = output.text();
+    if text.len() > 800 || more_than_15_newlines(&text) {
+        let mut content = MessageBuilder::new();
+        content.push(status_message);
+        if let Some(usage) = usage {
+            content.push_italic_safe(usage);
+        }
+        ctx.send(Reply {
+            content: content.0,
+            attachments: vec![Attachment {
                 filename: "output.txt".into(),
-            })
-            .content(status_message)
+                data: text.into(),
+            }],
         })
         .await?;
     } else {
         let mut message = MessageBuilder::new();
         message.push(status_message);
-        if output.is_empty() {
+        if text.is_empty() {
             message.push_italic("(no output)");
+        } else if output.has_stderr() {
+            message.push_codeblock_safe(output.ansi(), Some("ansi"));
         } else {
-            message.push_codeblock_safe(output, None);
+            message.push_codeblock_safe(text, None);
+        }
+        if let Some(usage) = usage {
+            message.push("\n").push_italic_safe(usage);
         }
         ctx.say(message.0).await?;
     }
@@ -168,11 +567,37 @@ async fn post_output(ctx: Context<'_>, output: &str, status: Option<i32>) -> Res
 /// as a complete program, otherwise the code will be evaluated as an \
 /// expression.
 ///
+/// Standard input can be provided in a code block labelled `input`, \
+/// additional files as attachments or code blocks labelled with a file \
+/// name, for instance ```` ```c++ util.h ````.
+///
+/// The compiler and C++ standard can be selected with `compiler=` and \
+/// `std=` options, followed by compiler flags, see `toolchains` command \
+/// for allowed ones. Libraries listed by `libraries` command are linked \
+/// when their headers are included.
+///
 /// Example: `!xb ceval std::string("Hello, ") + "world!"`
-pub async fn ceval(ctx: Context<'_>, #[rest] code: String) -> Result<()> {
+async fn ceval(ctx: Context<'_>, #[rest] code: String) -> Result<()> {
+    ceval_code(&ctx, parse_code(&code)).await
+}
+
+#[command(slash_command)]
+async fn ceval_slash(ctx: ApplicationContext<'_>) -> Result<()> {
+    if let Some(modal) = CodeModal::execute(ctx).await? {
+        ceval_code(&Context::Application(ctx), modal.parsed()).await?;
+    }
+    Ok(())
+}
+
+pub(crate) fn ceval_merged() -> poise::Command<Data, Error> {
+    with_modal(ceval(), ceval_slash())
+}
+
+async fn ceval_code(ctx: &impl Transport, parsed: Parsed<'_>) -> Result<()> {
     eval(
         ctx,
-        &code,
+        parsed,
+        "code",
         "int main",
         |rest| {
             let contains_return = rest.contains("return");
@@ -196,8 +621,20 @@ pub async fn ceval(ctx: Context<'_>, #[rest] code: String) -> Result<()> {
                 if contains_return { "" } else { "});" },
             )
         },
-        |opt| {
-            format!("mv code{{,.cpp}}; clang++ -std=c++17 -Wall -Wextra {opt} code.cpp && ./a.out")
+        CPP_OPTIONS,
+        |options, sources| {
+            let compiler = options.toolchain["compiler"];
+            let std = options.toolchain["std"];
+            let libraries = library::cpp_flags(sources);
+            let compile = command_line(&[
+                compiler,
+                std,
+                "-Wall -Wextra",
+                &options.flags,
+                "./*.cpp",
+                &libraries,
+            ]);
+            Script::compiled(format!("mv code{{,.cpp}}; {compile}"), "./a.out")
         },
     )
     .await
@@ -213,11 +650,38 @@ static FEATURES: Lazy<Regex> =
 /// interpreted as a complete program, otherwise the code will \
 /// be evaluated as an expression.
 ///
+/// Standard input can be provided in a code block labelled `input`. \
+/// Modules can be attached or provided in code blocks labelled with \
+/// a file name, for instance ```` ```rust util.rs ```` declared with \
+/// `mod util;` in a complete program.
+///
+/// The Rust channel and edition can be selected with `channel=` and \
+/// `edition=` options, followed by compiler flags, see `toolchains` \
+/// command for allowed ones. Crates listed by `libraries` command are \
+/// linked when code refers to them.
+///
 /// Example: `!xb rusteval format!("Hello, {}!", "world")`
-pub async fn rusteval(ctx: Context<'_>, #[rest] code: String) -> Result<()> {
+async fn rusteval(ctx: Context<'_>, #[rest] code: String) -> Result<()> {
+    rusteval_code(&ctx, parse_code(&code)).await
+}
+
+#[command(slash_command)]
+async fn rusteval_slash(ctx: ApplicationContext<'_>) -> Result<()> {
+    if let Some(modal) = CodeModal::execute(ctx).await? {
+        rusteval_code(&Context::Application(ctx), modal.parsed()).await?;
+    }
+    Ok(())
+}
+
+pub(crate) fn rusteval_merged() -> poise::Command<Data, Error> {
+    with_modal(rusteval(), rusteval_slash())
+}
+
+async fn rusteval_code(ctx: &impl Transport, parsed: Parsed<'_>) -> Result<()> {
     eval(
         ctx,
-        &code,
+        parsed,
+        "code",
         "fn main",
         |rest| {
             let regex_match = FEATURES.find(rest).unwrap();
@@ -243,10 +707,14 @@ pub async fn rusteval(ctx: Context<'_>, #[rest] code: String) -> Result<()> {
                 inner = inner,
             )
         },
-        |opt| {
-            format!(
-                "mv code{{,.rs}}; $RUST_NIGHTLY/bin/rustc --edition 2021 {opt} code.rs && ./code"
-            )
+        RUST_OPTIONS,
+        |options, sources| {
+            let channel = options.toolchain["channel"];
+            let edition = options.toolchain["edition"];
+            let crates = library::rust_flags(channel, sources);
+            let rustc = format!("{channel}/bin/rustc");
+            let compile = command_line(&[&rustc, edition, &options.flags, &crates, "code.rs"]);
+            Script::compiled(format!("mv code{{,.rs}}; {compile}"), "./code")
         },
     )
     .await
@@ -269,14 +737,48 @@ if last_expression:
 #[command(prefix_command, track_edits)]
 /// Evaluate Python code.
 ///
+/// Standard input can be provided in a code block labelled `input`. \
+/// Modules can be attached or provided in code blocks labelled with \
+/// a file name, for instance ```` ```py util.py ```` imported with \
+/// `import util`.
+///
+/// Interpreter flags can be provided before the code, see `toolchains` \
+/// command for allowed ones.
+///
 /// Example: `!xb pyeval print(2 + 2)`
-pub async fn pyeval(ctx: Context<'_>, #[rest] code: String) -> Result<()> {
+async fn pyeval(ctx: Context<'_>, #[rest] code: String) -> Result<()> {
+    pyeval_code(&ctx, parse_code(&code)).await
+}
+
+#[command(slash_command)]
+async fn pyeval_slash(ctx: ApplicationContext<'_>) -> Result<()> {
+    if let Some(modal) = CodeModal::execute(ctx).await? {
+        pyeval_code(&Context::Application(ctx), modal.parsed()).await?;
+    }
+    Ok(())
+}
+
+pub(crate) fn pyeval_merged() -> poise::Command<Data, Error> {
+    with_modal(pyeval(), pyeval_slash())
+}
+
+async fn pyeval_code(ctx: &impl Transport, parsed: Parsed<'_>) -> Result<()> {
     eval(
         ctx,
-        &code,
+        parsed,
+        "code",
         "",
         |_| unreachable!(),
-        |opt| format!("python3 {opt} -u -c '{PYTHON_EVALUATOR}'"),
+        PYTHON_OPTIONS,
+        |options, _| {
+            let evaluator = format!("'{PYTHON_EVALUATOR}'");
+            Script::new(command_line(&[
+                "python3",
+                &options.flags,
+                "-u -c",
+                &evaluator,
+            ]))
+        },
     )
     .await
 }
@@ -286,11 +788,20 @@ pub async fn pyeval(ctx: Context<'_>, #[rest] code: String) -> Result<()> {
 ///
 /// Example: `!xb ftfy âœ”`
 pub async fn ftfy(ctx: Context<'_>, #[rest] text: String) -> Result<()> {
+    fix_text(&ctx, &text).await
+}
+
+async fn fix_text(ctx: &impl Transport, text: &str) -> Result<()> {
+    if !ratelimit::allow(ctx, Resource::Sandbox).await? {
+        return Ok(());
+    }
     let Response { output, .. } = sandbox_request(
-        ctx,
+        ctx.data(),
         &Command {
-            stdin: &text,
+            stdin: text,
             code: "ftfy",
+            compile: None,
+            run: None,
             files: NoFiles {},
         },
     )
@@ -299,62 +810,17 @@ pub async fn ftfy(ctx: Context<'_>, #[rest] text: String) -> Result<()> {
     Ok(())
 }
 
-#[command(prefix_command, track_edits)]
-/// Compiles C code and outputs 6502 assembly.
-///
-/// Uses Godbolt Compiler Explorer and llvm-mos internally (https://godbolt.org/).
-///
-/// Example: `!xb casm unsigned char add1(unsigned char v) { return v + 1; }`
-pub async fn casm(ctx: Context<'_>, #[rest] code: String) -> Result<()> {
-    #[derive(Serialize)]
-    struct Compile<'a> {
-        source: &'a str,
-        options: Options,
-    }
-    #[derive(Serialize)]
-    #[serde(rename_all = "camelCase")]
-    struct Options {
-        user_arguments: String,
-    }
-    #[derive(Deserialize)]
-    struct Response {
-        code: Option<i32>,
-        stdout: Vec<Line>,
-        stderr: Vec<Line>,
-        asm: Vec<Line>,
-    }
-    #[derive(Deserialize)]
-    struct Line {
-        text: String,
-    }
-    let Parsed { options, code } = parse_code(&code);
-    let code = format!("#include <cstdint>\n{code}");
-    let user_arguments =
-        format!("-Os -fno-color-diagnostics -g0 -mcpu=mosw65816 --std=c++20 {options}");
-    let response: Response = ctx
-        .data()
-        .client
-        .post("https://godbolt.org/api/compiler/mos-nes-nrom-trunk/compile")
-        .header("Accept", "application/json")
-        .json(&Compile {
-            source: &code,
-            options: Options { user_arguments },
-        })
-        .send()
-        .await?
-        .json()
-        .await?;
-    let output: String = [&response.stdout, &response.stderr, &response.asm]
-        .into_iter()
-        .flatten()
-        .flat_map(|Line { text }| [text, "\n"])
-        .collect();
-    post_output(ctx, &output, response.code).await
-}
-
 #[cfg(test)]
 mod test {
-    use super::{parse_code, Parsed};
+    use super::{
+        ceval_code, fix_text, parse_code, post_output, pyeval_code, rusteval_code, Output, Parsed,
+        Stream,
+    };
+    use crate::ratelimit::{Limits, RateLimiter};
+    use crate::testing::{mock_sandbox, received_json, FakeTransport};
+    use crate::transport::{Attachment, Reply};
+    use crate::Data;
+    use serde_json::json;
 
     #[test]
     fn strip_code() {
@@ -363,6 +829,8 @@ mod test {
             Parsed {
                 options: "",
                 code: "test",
+                stdin: "",
+                files: vec![],
             },
         );
         assert_eq!(
@@ -370,6 +838,8 @@ mod test {
             Parsed {
                 options: "",
                 code: "code",
+                stdin: "",
+                files: vec![],
             },
         );
         assert_eq!(
@@ -377,6 +847,8 @@ mod test {
             Parsed {
                 options: "",
                 code: "foo",
+                stdin: "",
+                files: vec![],
             },
         );
         assert_eq!(
@@ -384,6 +856,8 @@ mod test {
             Parsed {
                 options: "",
                 code: "bar\n",
+                stdin: "",
+                files: vec![],
             },
         );
         assert_eq!(
@@ -391,6 +865,8 @@ mod test {
             Parsed {
                 options: "",
                 code: "example code here\n",
+                stdin: "",
+                files: vec![],
             },
         );
         assert_eq!(
@@ -398,6 +874,8 @@ mod test {
             Parsed {
                 options: "",
                 code: "example\n",
+                stdin: "",
+                files: vec![],
             },
         );
         assert_eq!(
@@ -405,6 +883,8 @@ mod test {
             Parsed {
                 options: "-Wall",
                 code: "hi\n",
+                stdin: "",
+                files: vec![],
             },
         );
         assert_eq!(
@@ -412,6 +892,8 @@ mod test {
             Parsed {
                 options: "-Wall",
                 code: "hi",
+                stdin: "",
+                files: vec![],
             },
         );
         assert_eq!(
@@ -419,7 +901,291 @@ mod test {
             Parsed {
                 options: "-Wall",
                 code: "hi\n",
+                stdin: "",
+                files: vec![],
+            },
+        );
+        assert_eq!(
+            parse_code("```py\nprint(input())\n```\n```input\nhello\n```"),
+            Parsed {
+                options: "",
+                code: "print(input())\n",
+                stdin: "hello\n",
+                files: vec![],
+            },
+        );
+        assert_eq!(
+            parse_code("-O ```c++\nhi\n``` ```input\n1 2\n```"),
+            Parsed {
+                options: "-O",
+                code: "hi\n",
+                stdin: "1 2\n",
+                files: vec![],
+            },
+        );
+        assert_eq!(
+            parse_code("```c++\n#include \"a.h\"\n```\n```c++ a.h\nint a;\n```"),
+            Parsed {
+                options: "",
+                code: "#include \"a.h\"\n",
+                stdin: "",
+                files: vec![("a.h", "int a;\n")],
+            },
+        );
+        assert_eq!(
+            parse_code("```py\nprint('```')\n```"),
+            Parsed {
+                options: "",
+                code: "print('```')\n",
+                stdin: "",
+                files: vec![],
             },
         );
     }
+
+    #[test]
+    fn output_streams() {
+        let output = Output::parse("a\x7FEb\x7FEc\x7FOd\x7F\x7Fe\x7FO");
+        assert_eq!(
+            output.segments,
+            [
+                (Stream::Stdout, "a".into()),
+                (Stream::Stderr, "bc".into()),
+                (Stream::Stdout, "d\x7Fe".into()),
+            ],
+        );
+        assert_eq!(output.text(), "abcd\x7Fe");
+        assert_eq!(output.ansi(), "a\x1B[31mbc\x1B[0md\x7Fe");
+        assert!(!Output::parse("\x7FOonly stdout").has_stderr());
+    }
+
+    #[tokio::test]
+    async fn rejects_file_names() {
+        let sandbox = mock_sandbox(json!({ "output": "", "status": 0 })).await;
+        assert_eq!(
+            parse_code("```c++\nhi\n```\n```c++ -o.cpp\nx\n```").files,
+            [],
+        );
+        for (name, message) in [
+            (
+                "-fplugin=x.so.cpp",
+                "File name -fplugin=x.so.cpp is not allowed",
+            ),
+            (
+                "code.cpp",
+                "File name code.cpp is reserved for the evaluated code",
+            ),
+        ] {
+            let transport = FakeTransport::with_sandbox(&sandbox).with_files(&[(name, "int x;")]);
+            let error = ceval_code(&transport, parse_code("1")).await.err().unwrap();
+            assert_eq!(error.to_string(), message);
+        }
+        assert!(received_json(&sandbox).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejected_input_keeps_quota() {
+        let sandbox = mock_sandbox(json!({ "output": "", "status": 0 })).await;
+        let transport = FakeTransport::new(Data {
+            sandbox_url: sandbox.uri(),
+            rate_limiter: RateLimiter::new(Limits {
+                user: "1/60".parse().unwrap(),
+                ..Limits::default()
+            }),
+            ..Data::default()
+        });
+        let reserved = parse_code("```c++\n1\n```\n```c++ code.h\nint x;\n```");
+        assert!(ceval_code(&transport, reserved).await.is_err());
+        ceval_code(&transport, parse_code("1")).await.unwrap();
+        assert_eq!(received_json(&sandbox).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn ceval_wraps_expression() {
+        let sandbox = mock_sandbox(json!({ "output": "\x7FO3", "status": 0 })).await;
+        let transport = FakeTransport::with_sandbox(&sandbox).with_files(&[("util.h", "int x;")]);
+        ceval_code(&transport, parse_code("1 + 2")).await.unwrap();
+        assert_eq!(transport.replies(), [Reply::text("```\n3\n```")]);
+        let [request] = &received_json(&sandbox).await[..] else {
+            panic!("expected a single sandbox request");
+        };
+        assert_eq!(request["stdin"], "");
+        assert!(request["code"].as_str().unwrap().contains("clang++"));
+        assert!(request["files"]["code"]["contents"]
+            .as_str()
+            .unwrap()
+            .contains("return ({1 + 2;});"));
+        assert_eq!(request["files"]["util.h"]["contents"], "int x;");
+    }
+
+    #[tokio::test]
+    async fn toolchain_selection() {
+        let sandbox = mock_sandbox(json!({ "output": "", "status": 0 })).await;
+        let transport = FakeTransport::with_sandbox(&sandbox);
+        ceval_code(&transport, parse_code("compiler=gcc std=c++20 -O2 `1`"))
+            .await
+            .unwrap();
+        rusteval_code(&transport, parse_code("channel=stable edition=2018 `1`"))
+            .await
+            .unwrap();
+        let requests = received_json(&sandbox).await;
+        assert_eq!(
+            requests[0]["code"],
+            "mv code{,.cpp}; g++ -std=c++20 -Wall -Wextra -O2 ./*.cpp && ./a.out",
+        );
+        assert_eq!(
+            requests[1]["code"],
+            "mv code{,.rs}; $RUST_STABLE/bin/rustc --edition 2018 code.rs && ./code",
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_options() {
+        let sandbox = mock_sandbox(json!({ "output": "", "status": 0 })).await;
+        let transport = FakeTransport::with_sandbox(&sandbox);
+        let error = pyeval_code(&transport, parse_code("; rm -rf / `1`"))
+            .await
+            .err()
+            .unwrap();
+        assert!(error
+            .to_string()
+            .starts_with("Option ; is not allowed, allowed options: -b -bb"));
+        assert!(received_json(&sandbox).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn pyeval_reports_status() {
+        let sandbox = mock_sandbox(json!({ "output": "\x7FEError", "status": 1 })).await;
+        let transport = FakeTransport::with_sandbox(&sandbox);
+        pyeval_code(
+            &transport,
+            parse_code("```py\nimport sys\nsys.exit(1)\n```"),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            transport.replies(),
+            [Reply::text(
+                "Exited with status code 1\n```ansi\n\x1B[31mError\x1B[0m\n```"
+            )],
+        );
+        let requests = received_json(&sandbox).await;
+        assert_eq!(
+            requests[0]["files"]["code"]["contents"],
+            "import sys\nsys.exit(1)\n",
+        );
+        assert!(requests[0]["code"]
+            .as_str()
+            .unwrap()
+            .starts_with("python3 -u -c '"));
+    }
+
+    #[tokio::test]
+    async fn compile_and_run_phases() {
+        let sandbox = mock_sandbox(json!({
+            "output": "",
+            "status": 1,
+            "phases": [
+                { "name": "compile", "stdout": "", "stderr": "error: expected ';'", "status": 1 },
+            ],
+        }))
+        .await;
+        let transport = FakeTransport::with_sandbox(&sandbox);
+        ceval_code(&transport, parse_code("1 +")).await.unwrap();
+        let requests = received_json(&sandbox).await;
+        assert_eq!(requests[0]["run"], "./a.out");
+        assert!(requests[0]["compile"]
+            .as_str()
+            .unwrap()
+            .starts_with("mv code{,.cpp}; clang++ -std=c++17"));
+        assert_eq!(
+            transport.replies(),
+            [Reply::text(
+                "Compilation failed\nExited with status code 1\n```ansi\n\x1B[31merror: expected ';'\x1B[0m\n```"
+            )],
+        );
+    }
+
+    #[tokio::test]
+    async fn phase_store_paths() {
+        let sandbox = mock_sandbox(json!({
+            "phases": [
+                {
+                    "name": "compile",
+                    "stderr": "/nix/store/abc-gcc-12.3.0/include/c++/12.3.0/vector:5: error",
+                    "status": 1,
+                },
+            ],
+        }))
+        .await;
+        let transport = FakeTransport::with_sandbox(&sandbox);
+        ceval_code(&transport, parse_code("1")).await.unwrap();
+        assert_eq!(
+            transport.replies(),
+            [Reply::text(
+                "Compilation failed\nExited with status code 1\n```ansi\n\x1B[31mvector:5: error\x1B[0m\n```"
+            )],
+        );
+    }
+
+    #[tokio::test]
+    async fn run_phase_signal() {
+        let sandbox = mock_sandbox(json!({
+            "phases": [
+                { "name": "compile", "stderr": "warning: unused\n", "status": 0 },
+                { "name": "run", "stdout": "start", "signal": 11, "time": 0.25, "memory": 3145728 },
+            ],
+        }))
+        .await;
+        let transport = FakeTransport::with_sandbox(&sandbox);
+        rusteval_code(&transport, parse_code("fn main() {}"))
+            .await
+            .unwrap();
+        assert_eq!(
+            transport.replies(),
+            [Reply::text(concat!(
+                "Segmentation fault (SIGSEGV)\n",
+                "```ansi\n\x1B[31mwarning: unused\n\x1B[0mstart\n```\n",
+                "_Used 0.25 s, 3.0 MiB._",
+            ))],
+        );
+    }
+
+    #[tokio::test]
+    async fn ftfy_sends_stdin() {
+        let sandbox = mock_sandbox(json!({ "output": "✔", "status": 0 })).await;
+        let transport = FakeTransport::with_sandbox(&sandbox);
+        fix_text(&transport, "âœ”").await.unwrap();
+        assert_eq!(transport.replies(), [Reply::text("✔")]);
+        let requests = received_json(&sandbox).await;
+        assert_eq!(
+            requests[0],
+            json!({ "stdin": "âœ”", "code": "ftfy", "files": {} }),
+        );
+    }
+
+    #[tokio::test]
+    async fn post_output_formatting() {
+        let transport = FakeTransport::default();
+        post_output(&transport, "", Some(0)).await.unwrap();
+        post_output(&transport, "loop", None).await.unwrap();
+        let long_output = "line\n".repeat(20);
+        post_output(&transport, &long_output, Some(0))
+            .await
+            .unwrap();
+        assert_eq!(
+            transport.replies(),
+            [
+                Reply::text("_(no output)_"),
+                Reply::text("Killed the process due to timeout\n```\nloop\n```"),
+                Reply {
+                    content: String::new(),
+                    attachments: vec![Attachment {
+                        filename: "output.txt".into(),
+                        data: long_output.into(),
+                    }],
+                },
+            ],
+        );
+    }
 }
@@ -0,0 +1,279 @@
+// SPDX-FileCopyrightText: 2022 - 2023 Konrad Borowski <konrad@borowski.pw>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::eval::{parse_code, post_output, Parsed};
+use crate::{Context, Data};
+use anyhow::Result;
+use poise::{command, AutocompleteChoice};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Languages supported by the `asm` command, as named by Compiler Explorer.
+const LANGUAGES: &[&str] = &["c", "c++", "rust"];
+
+#[derive(Serialize)]
+struct Compile<'a> {
+    source: &'a str,
+    options: Options,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Options {
+    user_arguments: String,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    code: Option<i32>,
+    stdout: Vec<Line>,
+    stderr: Vec<Line>,
+    asm: Vec<Line>,
+}
+
+#[derive(Deserialize)]
+struct Line {
+    text: String,
+}
+
+#[derive(Deserialize)]
+pub struct Compiler {
+    id: String,
+    name: String,
+    lang: String,
+}
+
+/// How long the compiler list is stored in the database.
+const COMPILERS_CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Returns compilers available in Compiler Explorer, fetching them on first use.
+async fn compilers(data: &Data) -> Result<&[Compiler]> {
+    let compilers = data
+        .godbolt_compilers
+        .get_or_try_init(|| async {
+            if let Some(json) = data.storage.cached("godbolt_compilers")? {
+                return anyhow::Ok(serde_json::from_str::<Vec<Compiler>>(&json)?);
+            }
+            let json = data
+                .client
+                .get(format!(
+                    "{}/api/compilers?fields=id,name,lang",
+                    data.godbolt_url
+                ))
+                .header("Accept", "application/json")
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await?;
+            // Parsed before caching, so that an error page isn't cached
+            let compilers = serde_json::from_str::<Vec<Compiler>>(&json)?;
+            data.storage
+                .cache("godbolt_compilers", &json, COMPILERS_CACHE_TTL)?;
+            Ok(compilers)
+        })
+        .await?;
+    Ok(compilers)
+}
+
+/// Compiles the code, returning the output and exit code.
+async fn compile_request(
+    data: &Data,
+    compiler: &str,
+    source: &str,
+    user_arguments: String,
+) -> Result<(String, Option<i32>)> {
+    let response: Response = data
+        .client
+        .post(format!(
+            "{}/api/compiler/{compiler}/compile",
+            data.godbolt_url
+        ))
+        .header("Accept", "application/json")
+        .json(&Compile {
+            source,
+            options: Options { user_arguments },
+        })
+        .send()
+        .await?
+        .json()
+        .await?;
+    let output = [&response.stdout, &response.stderr, &response.asm]
+        .into_iter()
+        .flatten()
+        .flat_map(|Line { text }| [text, "\n"])
+        .collect();
+    Ok((output, response.code))
+}
+
+async fn compile(
+    ctx: Context<'_>,
+    compiler: &str,
+    source: &str,
+    user_arguments: String,
+) -> Result<()> {
+    let (output, code) = compile_request(ctx.data(), compiler, source, user_arguments).await?;
+    post_output(&ctx, &output, code).await
+}
+
+#[command(prefix_command, track_edits)]
+/// Compiles C code and outputs 6502 assembly.
+///
+/// Uses Godbolt Compiler Explorer and llvm-mos internally (https://godbolt.org/).
+///
+/// Example: `!xb casm unsigned char add1(unsigned char v) { return v + 1; }`
+pub async fn casm(ctx: Context<'_>, #[rest] code: String) -> Result<()> {
+    let Parsed { options, code, .. } = parse_code(&code);
+    let code = format!("#include <cstdint>\n{code}");
+    let user_arguments =
+        format!("-Os -fno-color-diagnostics -g0 -mcpu=mosw65816 --std=c++20 {options}");
+    compile(ctx, "mos-nes-nrom-trunk", &code, user_arguments).await
+}
+
+#[command(prefix_command, slash_command, track_edits)]
+/// Compiles code and outputs assembly.
+///
+/// Compiles C, C++ or Rust code with a given Compiler Explorer compiler \
+/// (https://godbolt.org/). Compiler IDs are listed at \
+/// https://godbolt.org/api/compilers, slash command suggests them.
+///
+/// Examples:
+/// `!xb asm c++ g132 int square(int x) { return x * x; }`
+/// `!xb asm rust r1740 pub fn square(x: u32) -> u32 { x * x }`
+pub async fn asm(
+    ctx: Context<'_>,
+    #[description = "Language"]
+    #[autocomplete = "autocomplete_language"]
+    language: String,
+    #[description = "Compiler ID"]
+    #[autocomplete = "autocomplete_compiler"]
+    compiler: String,
+    #[description = "Code to compile"]
+    #[rest]
+    code: String,
+) -> Result<()> {
+    let known_compiler = compilers(ctx.data())
+        .await?
+        .iter()
+        .any(|c| c.id == compiler && c.lang == language);
+    if !LANGUAGES.contains(&language.as_str()) || !known_compiler {
+        ctx.say(format!(
+            concat!(
+                "Unrecognized {language} compiler {compiler}, ",
+                "please refer to list of supported compilers at ",
+                "https://godbolt.org/api/compilers."
+            ),
+            language = language,
+            compiler = compiler,
+        ))
+        .await?;
+        return Ok(());
+    }
+    let Parsed { options, code, .. } = parse_code(&code);
+    compile(ctx, &compiler, code, options.into()).await
+}
+
+async fn autocomplete_language<'a>(
+    _ctx: Context<'a>,
+    partial: &'a str,
+) -> impl Iterator<Item = String> + 'a {
+    LANGUAGES
+        .iter()
+        .filter(move |language| language.starts_with(partial))
+        .map(|language| String::from(*language))
+}
+
+async fn autocomplete_compiler<'a>(
+    ctx: Context<'a>,
+    partial: &'a str,
+) -> impl Iterator<Item = AutocompleteChoice<String>> + 'a {
+    let partial = partial.to_lowercase();
+    compilers(ctx.data())
+        .await
+        .unwrap_or_default()
+        .iter()
+        .filter(move |c| {
+            LANGUAGES.contains(&c.lang.as_str())
+                && (c.id.contains(&partial) || c.name.to_lowercase().contains(&partial))
+        })
+        .map(|c| AutocompleteChoice {
+            name: format!("{} ({})", c.name, c.lang),
+            value: c.id.clone(),
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compile_request, compilers};
+    use crate::Data;
+    use serde_json::json;
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn compile_output() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/compiler/g132/compile"))
+            .and(body_partial_json(json!({
+                "source": "int x;",
+                "options": { "userArguments": "-O2" },
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "code": 0,
+                "stdout": [],
+                "stderr": [{ "text": "warning" }],
+                "asm": [{ "text": "x:" }, { "text": "        .zero   4" }],
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let data = Data {
+            godbolt_url: server.uri(),
+            ..Data::default()
+        };
+        let output = compile_request(&data, "g132", "int x;", "-O2".into())
+            .await
+            .unwrap();
+        assert_eq!(output, ("warning\nx:\n        .zero   4\n".into(), Some(0)));
+    }
+
+    #[tokio::test]
+    async fn compilers_are_cached() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/compilers"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+                { "id": "g132", "name": "x86-64 gcc 13.2", "lang": "c++" },
+            ])))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let data = Data {
+            godbolt_url: server.uri(),
+            ..Data::default()
+        };
+        for _ in 0..2 {
+            let compilers = compilers(&data).await.unwrap();
+            assert_eq!(compilers.len(), 1);
+            assert_eq!(compilers[0].id, "g132");
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_compilers_are_not_cached() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/compilers"))
+            .respond_with(ResponseTemplate::new(502).set_body_string("<html>Bad Gateway</html>"))
+            .mount(&server)
+            .await;
+        let data = Data {
+            godbolt_url: server.uri(),
+            ..Data::default()
+        };
+        assert!(compilers(&data).await.is_err());
+        assert_eq!(data.storage.cached("godbolt_compilers").unwrap(), None);
+    }
+}
@@ -0,0 +1,172 @@
+// SPDX-FileCopyrightText: 2023 Konrad Borowski <konrad@borowski.pw>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::eval::{eval, parse_code, with_modal, CodeModal, Parsed, Script};
+use crate::toolchain::Allowlist;
+use crate::transport::Transport;
+use crate::{ApplicationContext, Context, Data};
+use anyhow::{bail, Context as _, Error, Result};
+use poise::{command, Command, Modal};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::{fs, io};
+
+/// Evaluators loaded from the language registry file.
+#[derive(Default)]
+pub struct Languages {
+    languages: BTreeMap<String, Language>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RegistryFile {
+    #[serde(default, rename = "language")]
+    languages: Vec<Language>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Language {
+    /// Name of the generated command, for instance `goeval`.
+    command: String,
+    description: String,
+    /// Name under which the code is stored in the sandbox.
+    file_name: String,
+    /// Shell command running the code, `{options}` is replaced with user options.
+    runner: String,
+    /// Options users can provide, `*` at the end matches any suffix.
+    #[serde(default)]
+    flags: Vec<String>,
+    wrapper: Option<Wrapper>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Wrapper {
+    /// When code contains this string it is interpreted as a complete program.
+    entry_point: String,
+    /// Program into which other code is inserted in place of `{code}`.
+    template: String,
+}
+
+impl Languages {
+    /// Loads the registry, a missing file is treated as an empty registry.
+    ///
+    /// Registry commands cannot reuse names of `builtin` commands.
+    pub fn load(path: &Path, builtin: &[&str]) -> Result<Self> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        let file: RegistryFile = toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        let mut languages = BTreeMap::new();
+        for language in file.languages {
+            if builtin.contains(&&*language.command) {
+                bail!(
+                    "Command {} in {} conflicts with a built-in command",
+                    language.command,
+                    path.display(),
+                );
+            }
+            if languages.contains_key(&language.command) {
+                bail!(
+                    "Command {} is defined multiple times in {}",
+                    language.command,
+                    path.display(),
+                );
+            }
+            languages.insert(language.command.clone(), language);
+        }
+        Ok(Self { languages })
+    }
+
+    /// Flags allowed for each registry command.
+    pub(crate) fn flags(&self) -> impl Iterator<Item = (&str, Vec<&str>)> {
+        self.languages.values().map(|language| {
+            let flags = language.flags.iter().map(String::as_str).collect();
+            (&*language.command, flags)
+        })
+    }
+
+    pub fn commands(&self) -> impl Iterator<Item = Command<Data, Error>> + '_ {
+        self.languages.values().map(|language| Command {
+            name: language.command.clone(),
+            qualified_name: language.command.clone(),
+            identifying_name: language.command.clone(),
+            description: Some(language.description.clone()),
+            ..with_modal(language_eval(), language_eval_slash())
+        })
+    }
+}
+
+#[command(prefix_command, track_edits)]
+async fn language_eval(ctx: Context<'_>, #[rest] code: String) -> Result<()> {
+    language_eval_code(&ctx, &ctx.command().name, parse_code(&code)).await
+}
+
+#[command(slash_command)]
+async fn language_eval_slash(ctx: ApplicationContext<'_>) -> Result<()> {
+    if let Some(modal) = CodeModal::execute(ctx).await? {
+        let name = &ctx.command.name;
+        language_eval_code(&Context::Application(ctx), name, modal.parsed()).await?;
+    }
+    Ok(())
+}
+
+async fn language_eval_code(ctx: &impl Transport, name: &str, parsed: Parsed<'_>) -> Result<()> {
+    let Language {
+        file_name,
+        runner,
+        flags,
+        wrapper,
+        ..
+    } = ctx
+        .data()
+        .languages
+        .languages
+        .get(name)
+        .with_context(|| format!("Language {name} is not in the registry"))?;
+    let entry_point = wrapper.as_ref().map_or("", |w| &w.entry_point);
+    let flags: Vec<_> = flags.iter().map(String::as_str).collect();
+    eval(
+        ctx,
+        parsed,
+        file_name,
+        entry_point,
+        |code| {
+            let Some(wrapper) = wrapper else {
+                unreachable!()
+            };
+            wrapper.template.replace("{code}", code)
+        },
+        Allowlist {
+            settings: &[],
+            flags: &flags,
+        },
+        |options, _| Script::new(runner.replace("{options}", &options.flags)),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::Languages;
+    use std::path::Path;
+
+    #[test]
+    fn example_registry_loads() {
+        let path = Path::new("languages.toml.example");
+        let languages = Languages::load(path, &["ceval"]).unwrap();
+        let names: Vec<_> = languages.commands().map(|command| command.name).collect();
+        assert_eq!(names, ["goeval", "hseval", "jseval"]);
+        let error = Languages::load(path, &["help", "jseval"]).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Command jseval in languages.toml.example conflicts with a built-in command",
+        );
+    }
+}
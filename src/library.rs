@@ -0,0 +1,207 @@
+// SPDX-FileCopyrightText: 2023 Konrad Borowski <konrad@borowski.pw>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::Context;
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use poise::command;
+use regex::Regex;
+use std::collections::BTreeSet;
+
+/// Rust crates compiled with each toolchain in its `crates` directory.
+///
+/// The sandbox builds them with cargo and copies the rlibs of these crates
+/// without cargo's hash suffix, as `crates/lib<name>.rlib`, so that they
+/// can be passed to `--extern`. Their dependencies are copied to the same
+/// directory unchanged, to be found through `-L dependency=`.
+const CRATES: &[&str] = &[
+    "ahash",
+    "anyhow",
+    "arrayvec",
+    "bitflags",
+    "byteorder",
+    "bytes",
+    "chrono",
+    "crossbeam",
+    "either",
+    "hashbrown",
+    "indexmap",
+    "itertools",
+    "lazy_static",
+    "libc",
+    "memchr",
+    "num",
+    "once_cell",
+    "parking_lot",
+    "rand",
+    "rayon",
+    "regex",
+    "serde",
+    "serde_json",
+    "smallvec",
+    "thiserror",
+    "tokio",
+];
+
+/// C++ library installed in `$CPP_LIBRARIES`.
+struct CppLibrary {
+    name: &'static str,
+    /// Directory containing headers of the library.
+    header: &'static str,
+    /// Additional compiler flags needed to use the library.
+    flags: &'static str,
+}
+
+const CPP_LIBRARIES: &[CppLibrary] = &[
+    CppLibrary {
+        name: "Boost",
+        header: "boost/",
+        flags: "",
+    },
+    CppLibrary {
+        name: "Eigen",
+        header: "Eigen/",
+        flags: "-I$CPP_LIBRARIES/include/eigen3",
+    },
+    CppLibrary {
+        name: "fmt",
+        header: "fmt/",
+        flags: "-lfmt",
+    },
+    CppLibrary {
+        name: "nlohmann/json",
+        header: "nlohmann/",
+        flags: "",
+    },
+    CppLibrary {
+        name: "range-v3",
+        header: "range/v3/",
+        flags: "",
+    },
+];
+
+static RUST_PATH: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?:^|[^\w:])([a-z_][a-z0-9_]*)\s*::|\bextern\s+crate\s+([a-z_][a-z0-9_]*)")
+        .unwrap()
+});
+
+static INCLUDE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"(?m)^\s*#\s*include\s*[<"]([^>"]+)[>"]"#).unwrap());
+
+/// Returns `rustc` flags linking crates used in sources.
+pub(crate) fn rust_flags(channel: &str, sources: &[&str]) -> String {
+    let used: BTreeSet<_> = sources
+        .iter()
+        .flat_map(|source| RUST_PATH.captures_iter(source))
+        .filter_map(|captures| captures.get(1).or_else(|| captures.get(2)))
+        .map(|name| name.as_str())
+        .filter(|name| CRATES.contains(name))
+        .collect();
+    if used.is_empty() {
+        return String::new();
+    }
+    let mut flags = format!("-L dependency={channel}/crates");
+    for name in used {
+        flags += &format!(" --extern {name}={channel}/crates/lib{name}.rlib");
+    }
+    flags
+}
+
+/// Returns compiler flags for C++ libraries included in sources.
+pub(crate) fn cpp_flags(sources: &[&str]) -> String {
+    let headers: Vec<_> = sources
+        .iter()
+        .flat_map(|source| INCLUDE.captures_iter(source))
+        .map(|captures| captures.get(1).unwrap().as_str())
+        .collect();
+    let used: Vec<_> = CPP_LIBRARIES
+        .iter()
+        .filter(|library| {
+            headers
+                .iter()
+                .any(|header| header.starts_with(library.header))
+        })
+        .collect();
+    if used.is_empty() {
+        return String::new();
+    }
+    // The rpath lets programs find shared libraries without `LD_LIBRARY_PATH`
+    let mut flags =
+        String::from("-I$CPP_LIBRARIES/include -L$CPP_LIBRARIES/lib -Wl,-rpath,$CPP_LIBRARIES/lib");
+    for library in used {
+        if !library.flags.is_empty() {
+            flags += " ";
+            flags += library.flags;
+        }
+    }
+    flags
+}
+
+fn describe() -> String {
+    let libraries: Vec<_> = CPP_LIBRARIES.iter().map(|library| library.name).collect();
+    format!(
+        "**rusteval** crates: {}\n**ceval** libraries: {}",
+        CRATES.join(", "),
+        libraries.join(", "),
+    )
+}
+
+/// List libraries available to code evaluators.
+///
+/// Libraries are linked when used, Rust crates when code refers to them \
+/// with a path like `itertools::Itertools`, C++ libraries when their \
+/// headers are included.
+#[command(prefix_command, slash_command)]
+pub async fn libraries(ctx: Context<'_>) -> Result<()> {
+    ctx.say(describe()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{cpp_flags, describe, rust_flags};
+
+    #[test]
+    fn rust_crates() {
+        let code =
+            "use itertools::Itertools;\nfn main() { serde_json::json!({}); std::mem::drop(1); }";
+        assert_eq!(
+            rust_flags("$RUST_NIGHTLY", &[code]),
+            concat!(
+                "-L dependency=$RUST_NIGHTLY/crates",
+                " --extern itertools=$RUST_NIGHTLY/crates/libitertools.rlib",
+                " --extern serde_json=$RUST_NIGHTLY/crates/libserde_json.rlib",
+            ),
+        );
+        assert_eq!(
+            rust_flags("$RUST_STABLE", &["extern crate rand;", "fn f() {}"]),
+            "-L dependency=$RUST_STABLE/crates --extern rand=$RUST_STABLE/crates/librand.rlib",
+        );
+        assert_eq!(rust_flags("$RUST_STABLE", &["my::regex::f()"]), "");
+    }
+
+    #[test]
+    fn cpp_libraries() {
+        assert_eq!(
+            cpp_flags(&[
+                "#include <fmt/core.h>\n#include <vector>",
+                "# include \"Eigen/Dense\""
+            ]),
+            concat!(
+                "-I$CPP_LIBRARIES/include -L$CPP_LIBRARIES/lib -Wl,-rpath,$CPP_LIBRARIES/lib",
+                " -I$CPP_LIBRARIES/include/eigen3 -lfmt",
+            ),
+        );
+        assert_eq!(
+            cpp_flags(&["#include <boost/optional.hpp>"]),
+            "-I$CPP_LIBRARIES/include -L$CPP_LIBRARIES/lib -Wl,-rpath,$CPP_LIBRARIES/lib",
+        );
+        assert_eq!(cpp_flags(&["#include <string>"]), "");
+    }
+
+    #[test]
+    fn description() {
+        assert!(describe().contains("itertools"));
+    }
+}
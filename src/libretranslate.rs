@@ -0,0 +1,238 @@
+// SPDX-FileCopyrightText: 2023 Konrad Borowski <konrad@borowski.pw>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::translator::{Language, Languages, Options, TagHandling, Translation, Translator};
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tokio::sync::OnceCell;
+
+#[derive(Deserialize)]
+struct LibreLanguage {
+    code: String,
+    name: String,
+    targets: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct TranslateRequest<'a> {
+    q: &'a str,
+    source: &'a str,
+    target: &'a str,
+    format: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    api_key: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct DetectedLanguage {
+    language: String,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TranslateResponse {
+    #[serde(rename_all = "camelCase")]
+    Translation {
+        translated_text: String,
+        detected_language: Option<DetectedLanguage>,
+    },
+    Error {
+        error: String,
+    },
+}
+
+/// Client for a LibreTranslate instance (https://libretranslate.com/).
+pub struct LibreTranslate {
+    client: Client,
+    url: String,
+    api_key: Option<String>,
+    languages: OnceCell<Languages>,
+}
+
+impl LibreTranslate {
+    pub fn new(client: Client, url: String, api_key: Option<String>) -> Self {
+        Self {
+            client,
+            url,
+            api_key,
+            languages: OnceCell::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Translator for LibreTranslate {
+    fn name(&self) -> &'static str {
+        "libretranslate"
+    }
+
+    fn default_target(&self) -> &'static str {
+        "en"
+    }
+
+    async fn languages(&self) -> Result<Languages> {
+        let languages = self
+            .languages
+            .get_or_try_init(|| async {
+                let languages: Vec<LibreLanguage> = self
+                    .client
+                    .get(format!("{}/languages", self.url))
+                    .send()
+                    .await?
+                    .json()
+                    .await?;
+                let source: Vec<Language> = languages
+                    .iter()
+                    .map(|language| Language {
+                        code: language.code.clone(),
+                        name: language.name.clone(),
+                    })
+                    .collect();
+                // Targets are listed by code only, names come from source languages
+                let target = source
+                    .iter()
+                    .filter(|language| {
+                        languages
+                            .iter()
+                            .any(|source| source.targets.contains(&language.code))
+                    })
+                    .cloned()
+                    .collect();
+                anyhow::Ok(Languages { source, target })
+            })
+            .await?;
+        Ok(languages.clone())
+    }
+
+    async fn translate(
+        &self,
+        text: &str,
+        source: Option<&str>,
+        target: &str,
+        options: &Options,
+    ) -> Result<Translation> {
+        if options.formality.is_some()
+            || options.glossary_id.is_some()
+            || options.preserve_formatting
+            || options.context.is_some()
+        {
+            bail!("LibreTranslate doesn't support formality, glossaries, context or preserving formatting.");
+        }
+        let format = match options.tag_handling {
+            None => "text",
+            Some(TagHandling::Html) => "html",
+            Some(TagHandling::Xml) => bail!("LibreTranslate doesn't support XML."),
+        };
+        let response = self
+            .client
+            .post(format!("{}/translate", self.url))
+            .json(&TranslateRequest {
+                q: text,
+                source: source.unwrap_or("auto"),
+                target,
+                format,
+                api_key: self.api_key.as_deref(),
+            })
+            .send()
+            .await?
+            .json()
+            .await?;
+        match response {
+            TranslateResponse::Translation {
+                translated_text,
+                detected_language,
+            } => Ok(Translation {
+                text: translated_text,
+                detected_source: detected_language.map(|detected| detected.language),
+                notice: None,
+            }),
+            TranslateResponse::Error { error } => bail!("LibreTranslate error: {error}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::LibreTranslate;
+    use crate::translator::{Language, Options, Translator};
+    use reqwest::Client;
+    use serde_json::json;
+    use wiremock::matchers::{body_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn translate() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/translate"))
+            .and(body_json(json!({
+                "q": "Bonjour",
+                "source": "auto",
+                "target": "en",
+                "format": "text",
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "translatedText": "Hello",
+                "detectedLanguage": { "confidence": 90, "language": "fr" },
+            })))
+            .mount(&server)
+            .await;
+        let translator = LibreTranslate::new(Client::new(), server.uri(), None);
+        let translation = translator
+            .translate("Bonjour", None, "en", &Options::default())
+            .await
+            .unwrap();
+        assert_eq!(translation.text, "Hello");
+        assert_eq!(translation.detected_source.as_deref(), Some("fr"));
+    }
+
+    #[tokio::test]
+    async fn error() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                "error": "xx is not supported",
+            })))
+            .mount(&server)
+            .await;
+        let translator = LibreTranslate::new(Client::new(), server.uri(), None);
+        let error = translator
+            .translate("Bonjour", None, "xx", &Options::default())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "LibreTranslate error: xx is not supported"
+        );
+    }
+
+    #[tokio::test]
+    async fn languages() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/languages"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+                { "code": "en", "name": "English", "targets": ["en"] },
+                { "code": "pl", "name": "Polish", "targets": ["en"] },
+            ])))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let translator = LibreTranslate::new(Client::new(), server.uri(), None);
+        for _ in 0..2 {
+            let languages = translator.languages().await.unwrap();
+            let codes = |list: &[Language]| {
+                list.iter()
+                    .map(|language| language.code.clone())
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(codes(&languages.source), ["en", "pl"]);
+            assert_eq!(codes(&languages.target), ["en"]);
+            assert_eq!(languages.source[1].name, "Polish");
+        }
+    }
+}
@@ -2,39 +2,88 @@
 //
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
+mod autotranslate;
+mod config;
+mod deepl;
 mod eval;
+mod godbolt;
 mod help;
+mod language;
+mod library;
+mod libretranslate;
 mod ping;
 mod png;
+mod prefix;
+mod ratelimit;
 mod register;
+mod rules;
+mod snippet;
 mod source;
+mod stats;
+mod storage;
+#[cfg(test)]
+mod testing;
+mod toolchain;
 mod trans;
+mod translator;
+mod transport;
 
-use anyhow::Error;
+use anyhow::{Context as _, Error};
+use config::Config;
+use deepl::{DeepL, UsageTracker};
+use godbolt::Compiler;
+use language::Languages;
+use libretranslate::LibreTranslate;
 use log::error;
-use poise::{
-    EditTracker, Framework, FrameworkError, FrameworkOptions, Prefix, PrefixFrameworkOptions,
-};
+use poise::{EditTracker, Framework, FrameworkError, FrameworkOptions, PrefixFrameworkOptions};
+use ratelimit::RateLimiter;
 use reqwest::Client;
 use serenity::model::gateway::GatewayIntents;
 use std::env;
+use std::process;
 use std::time::Duration;
+use storage::Storage;
+use tokio::sync::OnceCell;
+use translator::Translators;
 
 type Context<'a> = poise::Context<'a, Data, Error>;
+type ApplicationContext<'a> = poise::ApplicationContext<'a, Data, Error>;
 
 pub struct Data {
     sandbox_url: String,
-    deepl_auth_key: String,
+    godbolt_url: String,
+    languages: Languages,
+    rate_limiter: RateLimiter,
+    translators: Translators,
+    storage: Storage,
+    /// Default command prefixes, the first one is shown to users.
+    prefixes: Vec<String>,
+    auto_translate: bool,
+    godbolt_compilers: OnceCell<Vec<Compiler>>,
     client: Client,
 }
 
 async fn on_error(error: FrameworkError<'_, Data, anyhow::Error>) {
     let result = match error {
+        // Prefixes chosen by servers may be shared with other bots
+        FrameworkError::UnknownCommand {
+            msg,
+            prefix,
+            framework,
+            ..
+        } if prefix::is_guild_prefix(framework.user_data, msg.guild_id.map(|id| id.0), prefix) => {
+            Ok(())
+        }
         FrameworkError::UnknownCommand { ctx, msg, .. } => msg
             .channel_id
             .say(ctx, "Unknown command.")
             .await
             .map(|_| ()),
+        // Raised by rules::check, the only check without an error
+        FrameworkError::CommandCheckFailed { ctx, error: None } => ctx
+            .send(|m| m.content("This command is disabled here.").ephemeral(true))
+            .await
+            .map(|_| ()),
         _ => poise::builtins::on_error(error).await,
     };
     if let Err(e) = result {
@@ -42,48 +91,122 @@ async fn on_error(error: FrameworkError<'_, Data, anyhow::Error>) {
     }
 }
 
+/// Reports an error preventing the bot from starting and exits.
+fn or_exit<T>(result: anyhow::Result<T>) -> T {
+    result.unwrap_or_else(|e| {
+        error!("{e:?}");
+        process::exit(1);
+    })
+}
+
 #[tokio::main]
 async fn main() {
     dotenv::dotenv().ok();
     env_logger::init();
-    let token = env::var("DISCORD_TOKEN").expect("Expected a token in the environment");
+    let config_file = env::var("XBOT_CONFIG").unwrap_or_else(|_| "config.toml".into());
+    let config = or_exit(Config::load(&config_file));
+    let storage = or_exit(
+        Storage::open(&config.database)
+            .with_context(|| format!("Cannot open database {}", config.database.display())),
+    );
+    let client = Client::new();
+    let translators = Translators {
+        deepl: config.deepl.map(|deepl| {
+            DeepL::new(
+                client.clone(),
+                deepl.url,
+                deepl.auth_key,
+                UsageTracker::new(deepl.warn_threshold, deepl.refuse_threshold),
+            )
+        }),
+        libretranslate: config.libretranslate.map(|libretranslate| {
+            LibreTranslate::new(client.clone(), libretranslate.url, libretranslate.api_key)
+        }),
+    };
+    let features = config.features;
+    let mut commands = vec![
+        help::help(),
+        eval::ceval_merged(),
+        eval::rusteval_merged(),
+        eval::pyeval_merged(),
+        eval::ftfy(),
+        toolchain::toolchains(),
+        library::libraries(),
+    ];
+    if features.godbolt {
+        commands.extend([godbolt::casm(), godbolt::asm()]);
+    }
+    if !translators.is_empty() {
+        commands.extend([
+            trans::trans_merged(),
+            trans::translate_message(),
+            trans::translator(),
+        ]);
+        if features.auto_translate {
+            commands.push(autotranslate::autotranslate());
+        }
+    }
+    if translators.deepl.is_some() {
+        commands.extend([trans::usage(), trans::glossary()]);
+    }
+    if features.snippets {
+        commands.push(snippet::snippet());
+    }
+    commands.extend([
+        prefix::prefix(),
+        rules::commands(),
+        source::source(),
+        // Hidden commands
+        register::register(),
+        png::png(),
+        ping::ping(),
+        stats::stats(),
+    ]);
+    let builtin: Vec<_> = commands.iter().map(|command| &*command.name).collect();
+    let languages = or_exit(Languages::load(&config.languages_file, &builtin));
+    commands.extend(languages.commands());
+    let mut prefixes = vec![config.prefixes.default];
+    prefixes.extend(config.prefixes.additional);
     Framework::builder()
         .options(FrameworkOptions {
-            commands: vec![
-                help::help(),
-                eval::ceval(),
-                eval::rusteval(),
-                eval::pyeval(),
-                eval::ftfy(),
-                eval::casm(),
-                trans::trans_merged(),
-                source::source(),
-                // Hidden commands
-                register::register(),
-                png::png(),
-                ping::ping(),
-            ],
+            commands,
             prefix_options: PrefixFrameworkOptions {
-                prefix: Some("!xb ".into()),
-                additional_prefixes: vec![Prefix::Literal(".xb ")],
+                // Default prefixes are handled by prefix::strip_prefix,
+                // as servers can replace them
+                stripped_dynamic_prefix: Some(prefix::strip_prefix),
                 edit_tracker: Some(EditTracker::for_timespan(Duration::from_secs(300))),
                 ..Default::default()
             },
             on_error: |e| Box::pin(on_error(e)),
+            post_command: |ctx| Box::pin(stats::record(ctx)),
+            command_check: Some(|ctx| Box::pin(rules::check(ctx))),
+            event_handler: |ctx, event, _framework, data| {
+                Box::pin(autotranslate::handle_event(ctx, event, data))
+            },
             ..Default::default()
         })
-        .token(token)
+        .token(config.discord_token)
         .intents(
             GatewayIntents::GUILD_MESSAGES
                 | GatewayIntents::DIRECT_MESSAGES
                 | GatewayIntents::MESSAGE_CONTENT,
         )
-        .setup(|_ctx, _ready, _framework| {
-            Box::pin(async {
+        .setup(move |_ctx, _ready, _framework| {
+            Box::pin(async move {
+                if let Some(deepl) = &translators.deepl {
+                    deepl.refresh_languages().await;
+                }
                 Ok(Data {
-                    sandbox_url: env::var("SANDBOX_URL")?,
-                    deepl_auth_key: env::var("DEEPL_AUTH_KEY")?,
-                    client: Client::new(),
+                    sandbox_url: config.sandbox.url,
+                    godbolt_url: config.godbolt.url,
+                    languages,
+                    rate_limiter: RateLimiter::new(config.rate_limits),
+                    translators,
+                    storage,
+                    prefixes,
+                    auto_translate: features.auto_translate,
+                    godbolt_compilers: OnceCell::new(),
+                    client,
                 })
             })
         })
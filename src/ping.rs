@@ -2,6 +2,7 @@
 //
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
+use crate::transport::{Reply, Transport};
 use crate::Context;
 use anyhow::Result;
 use poise::command;
@@ -10,12 +11,31 @@ use std::time::Instant;
 /// Get the link to the source code for this bot.
 #[command(prefix_command, hide_in_help)]
 pub async fn ping(ctx: Context<'_>) -> Result<()> {
+    pong(&ctx).await
+}
+
+async fn pong(ctx: &impl Transport) -> Result<()> {
     let now = Instant::now();
-    ctx.say("Pong!")
-        .await?
-        .edit(ctx, |b| {
-            b.content(format!("Pong! Took {:?}.", now.elapsed()))
-        })
-        .await?;
-    Ok(())
+    let message = ctx.say("Pong!".into()).await?;
+    ctx.edit(
+        &message,
+        Reply::text(format!("Pong! Took {:?}.", now.elapsed())),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::pong;
+    use crate::testing::FakeTransport;
+
+    #[tokio::test]
+    async fn pong_is_edited() {
+        let transport = FakeTransport::default();
+        pong(&transport).await.unwrap();
+        let replies = transport.replies();
+        assert_eq!(replies.len(), 1);
+        assert!(replies[0].content.starts_with("Pong! Took "));
+        assert_eq!(transport.replies(), replies);
+    }
 }
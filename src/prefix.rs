@@ -0,0 +1,210 @@
+// SPDX-FileCopyrightText: 2023 Konrad Borowski <konrad@borowski.pw>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::storage::GuildPrefix;
+use crate::{Context, Data};
+use anyhow::Result;
+use poise::{command, BoxFuture};
+use serenity::client::Context as SerenityContext;
+use serenity::model::channel::Message;
+
+/// Longest prefix a server can set.
+const MAX_PREFIX_LENGTH: usize = 16;
+
+/// Splits the prefix off a message, accepting the prefix chosen by the server
+/// and the default prefixes, unless the server replaced them. The longest
+/// matching prefix wins, so that a server prefix like `!` doesn't shadow
+/// the default `!xb `.
+pub fn split_prefix<'a>(
+    data: &Data,
+    guild_id: Option<u64>,
+    content: &'a str,
+) -> Result<Option<(&'a str, &'a str)>> {
+    let guild_prefix = match guild_id {
+        Some(guild_id) => data.storage.guild_prefix(guild_id)?,
+        None => None,
+    };
+    let mut prefixes = Vec::new();
+    if let Some(guild_prefix) = &guild_prefix {
+        prefixes.push(&*guild_prefix.prefix);
+    }
+    if !guild_prefix
+        .as_ref()
+        .is_some_and(|prefix| prefix.replaces_default)
+    {
+        prefixes.extend(data.prefixes.iter().map(String::as_str));
+    }
+    Ok(prefixes
+        .into_iter()
+        .filter(|prefix| content.starts_with(prefix))
+        .max_by_key(|prefix| prefix.len())
+        .map(|prefix| content.split_at(prefix.len())))
+}
+
+pub fn strip_prefix<'a>(
+    _: &'a SerenityContext,
+    msg: &'a Message,
+    data: &'a Data,
+) -> BoxFuture<'a, Result<Option<(&'a str, &'a str)>>> {
+    Box::pin(async move { split_prefix(data, msg.guild_id.map(|id| id.0), &msg.content) })
+}
+
+/// Checks whether the prefix is the one chosen by the server. Unknown
+/// commands with such prefixes are likely meant for other bots.
+pub fn is_guild_prefix(data: &Data, guild_id: Option<u64>, prefix: &str) -> bool {
+    let Some(guild_id) = guild_id else {
+        return false;
+    };
+    matches!(
+        data.storage.guild_prefix(guild_id),
+        Ok(Some(guild_prefix)) if guild_prefix.prefix == prefix,
+    )
+}
+
+/// Show the command prefix of this server.
+///
+/// Servers can set a prefix that works in addition to the default one, \
+/// or replace the default one, for instance when other bots already use \
+/// it. Prefixes ending with a space need to be quoted.
+///
+/// Examples:
+/// `!xb prefix set ?`
+/// `!xb prefix set "xb! "`
+/// `!xb prefix replace ?`
+/// `!xb prefix reset`
+#[command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    subcommands("prefix_set", "prefix_replace", "prefix_reset")
+)]
+pub async fn prefix(ctx: Context<'_>) -> Result<()> {
+    let guild_id = ctx.guild_id().map_or(0, |id| id.0);
+    let default = ctx.data().prefixes.first().map_or("", String::as_str);
+    let message = match ctx.data().storage.guild_prefix(guild_id)? {
+        Some(GuildPrefix {
+            prefix,
+            replaces_default: false,
+        }) => format!("This server uses `{prefix}` prefix in addition to `{default}`."),
+        Some(GuildPrefix {
+            prefix,
+            replaces_default: true,
+        }) => format!("This server uses `{prefix}` prefix instead of `{default}`."),
+        None => format!("This server uses `{default}` prefix."),
+    };
+    ctx.say(message).await?;
+    Ok(())
+}
+
+/// Set the command prefix of this server, in addition to the default one.
+#[command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    rename = "set"
+)]
+async fn prefix_set(ctx: Context<'_>, #[description = "New prefix"] prefix: String) -> Result<()> {
+    set_prefix(ctx, prefix, false).await
+}
+
+/// Set the command prefix of this server, replacing the default one.
+#[command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    rename = "replace"
+)]
+async fn prefix_replace(
+    ctx: Context<'_>,
+    #[description = "New prefix"] prefix: String,
+) -> Result<()> {
+    set_prefix(ctx, prefix, true).await
+}
+
+async fn set_prefix(ctx: Context<'_>, prefix: String, replaces_default: bool) -> Result<()> {
+    if prefix.trim().is_empty() || prefix.starts_with(char::is_whitespace) {
+        ctx.say("Prefix cannot be empty or start with whitespace.")
+            .await?;
+        return Ok(());
+    }
+    if prefix.chars().count() > MAX_PREFIX_LENGTH {
+        ctx.say(format!(
+            "Prefix cannot be longer than {MAX_PREFIX_LENGTH} characters."
+        ))
+        .await?;
+        return Ok(());
+    }
+    let guild_id = ctx.guild_id().map_or(0, |id| id.0);
+    let message = if replaces_default {
+        format!("Commands can now be invoked only with `{prefix}` prefix.")
+    } else {
+        format!("Commands can now be invoked with `{prefix}` prefix.")
+    };
+    ctx.data().storage.set_guild_prefix(
+        guild_id,
+        Some(&GuildPrefix {
+            prefix,
+            replaces_default,
+        }),
+    )?;
+    ctx.say(message).await?;
+    Ok(())
+}
+
+/// Remove the command prefix of this server, restoring the default one.
+#[command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    rename = "reset"
+)]
+async fn prefix_reset(ctx: Context<'_>) -> Result<()> {
+    let guild_id = ctx.guild_id().map_or(0, |id| id.0);
+    ctx.data().storage.set_guild_prefix(guild_id, None)?;
+    ctx.say("Removed the prefix of this server.").await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_guild_prefix, split_prefix};
+    use crate::storage::GuildPrefix;
+    use crate::Data;
+
+    #[test]
+    fn prefixes() {
+        let data = Data::default();
+        assert_eq!(
+            split_prefix(&data, Some(1), "!xb ping").unwrap(),
+            Some(("!xb ", "ping")),
+        );
+        assert_eq!(split_prefix(&data, Some(1), "!ping").unwrap(), None);
+        let mut prefix = GuildPrefix {
+            prefix: "!".into(),
+            replaces_default: false,
+        };
+        data.storage.set_guild_prefix(1, Some(&prefix)).unwrap();
+        assert_eq!(
+            split_prefix(&data, Some(1), "!ping").unwrap(),
+            Some(("!", "ping")),
+        );
+        assert_eq!(
+            split_prefix(&data, Some(1), "!xb ping").unwrap(),
+            Some(("!xb ", "ping")),
+        );
+        assert!(is_guild_prefix(&data, Some(1), "!"));
+        assert!(!is_guild_prefix(&data, Some(2), "!"));
+        prefix.replaces_default = true;
+        data.storage.set_guild_prefix(1, Some(&prefix)).unwrap();
+        assert_eq!(split_prefix(&data, Some(1), "?xb ping").unwrap(), None);
+        assert_eq!(
+            split_prefix(&data, None, "!xb ping").unwrap(),
+            Some(("!xb ", "ping")),
+        );
+    }
+}
@@ -0,0 +1,313 @@
+// SPDX-FileCopyrightText: 2023 Konrad Borowski <konrad@borowski.pw>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::transport::Transport;
+use anyhow::{anyhow, Error, Result};
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Service protected by a rate limit.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum Resource {
+    Sandbox,
+    Translation,
+    /// Automatic translations, limited separately so that chatting in
+    /// a translated channel doesn't use up the quota of `trans`.
+    AutoTranslation,
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum Scope {
+    User,
+    Channel,
+    Guild,
+}
+
+/// Where a command was invoked.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Origin {
+    pub user: u64,
+    pub channel: u64,
+    pub guild: Option<u64>,
+}
+
+/// Allows `requests` requests every `per`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Quota {
+    requests: usize,
+    per: Duration,
+}
+
+impl FromStr for Quota {
+    type Err = Error;
+
+    /// Parses quotas in `requests/seconds` format, for instance `5/60`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (requests, seconds) = s
+            .split_once('/')
+            .ok_or_else(|| anyhow!("Expected quota in requests/seconds format, got {s}"))?;
+        Ok(Self {
+            requests: requests.trim().parse()?,
+            per: Duration::from_secs(seconds.trim().parse()?),
+        })
+    }
+}
+
+impl TryFrom<String> for Quota {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub user: Quota,
+    pub channel: Quota,
+    pub guild: Quota,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        let minute = Duration::from_secs(60);
+        Self {
+            user: Quota {
+                requests: 5,
+                per: minute,
+            },
+            channel: Quota {
+                requests: 15,
+                per: minute,
+            },
+            guild: Quota {
+                requests: 30,
+                per: minute,
+            },
+        }
+    }
+}
+
+/// Error returned when a quota is exhausted.
+#[derive(Debug, PartialEq, Eq)]
+pub struct RateLimited {
+    scope: Scope,
+    retry_after: Duration,
+}
+
+impl Display for RateLimited {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let who = match self.scope {
+            Scope::User => "You are",
+            Scope::Channel => "This channel is",
+            Scope::Guild => "This server is",
+        };
+        // Rounding up, so that retrying after the given time always works
+        let seconds = self.retry_after.as_secs() + u64::from(self.retry_after.subsec_nanos() > 0);
+        write!(
+            f,
+            "Slow down! {who} sending too many requests, try again in {seconds} seconds."
+        )
+    }
+}
+
+/// Sliding window rate limiter.
+pub struct RateLimiter {
+    limits: Limits,
+    requests: Mutex<HashMap<(Resource, Scope, u64), VecDeque<Instant>>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            requests: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn quota(&self, scope: Scope) -> Quota {
+        match scope {
+            Scope::User => self.limits.user,
+            Scope::Channel => self.limits.channel,
+            Scope::Guild => self.limits.guild,
+        }
+    }
+
+    fn check_at(
+        &self,
+        resource: Resource,
+        origin: Origin,
+        now: Instant,
+    ) -> Result<(), RateLimited> {
+        let buckets = [
+            (Scope::User, Some(origin.user)),
+            (Scope::Channel, Some(origin.channel)),
+            (Scope::Guild, origin.guild),
+        ];
+        let mut requests = self.requests.lock().unwrap();
+        // Expiring requests in every bucket, so that buckets of users
+        // who stopped sending requests don't accumulate
+        requests.retain(|&(_, scope, _), times| {
+            let per = self.quota(scope).per;
+            while times
+                .front()
+                .is_some_and(|&time| now.duration_since(time) >= per)
+            {
+                times.pop_front();
+            }
+            !times.is_empty()
+        });
+        for &(scope, id) in &buckets {
+            let Some(id) = id else { continue };
+            let quota = self.quota(scope);
+            let times = requests.get(&(resource, scope, id));
+            if times.map_or(0, VecDeque::len) >= quota.requests {
+                let retry_after = times.and_then(VecDeque::front).map_or(quota.per, |&time| {
+                    quota.per.saturating_sub(now.duration_since(time))
+                });
+                return Err(RateLimited { scope, retry_after });
+            }
+        }
+        for &(scope, id) in &buckets {
+            if let Some(id) = id {
+                requests
+                    .entry((resource, scope, id))
+                    .or_default()
+                    .push_back(now);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn check(&self, resource: Resource, origin: Origin) -> Result<(), RateLimited> {
+        self.check_at(resource, origin, Instant::now())
+    }
+}
+
+/// Records a request, telling the user to slow down and returning `false`
+/// when a quota is exhausted.
+pub async fn allow(ctx: &impl Transport, resource: Resource) -> Result<bool> {
+    match ctx.data().rate_limiter.check(resource, ctx.origin()) {
+        Ok(()) => Ok(true),
+        Err(rate_limited) => {
+            ctx.say(rate_limited.to_string()).await?;
+            Ok(false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Limits, Origin, Quota, RateLimited, RateLimiter, Resource, Scope};
+    use std::time::{Duration, Instant};
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    fn limiter() -> RateLimiter {
+        RateLimiter::new(Limits {
+            user: "2/10".parse().unwrap(),
+            channel: "3/10".parse().unwrap(),
+            guild: "100/10".parse().unwrap(),
+        })
+    }
+
+    fn user(user: u64) -> Origin {
+        Origin {
+            user,
+            channel: 1,
+            guild: Some(1),
+        }
+    }
+
+    #[test]
+    fn quota_parsing() {
+        assert_eq!(
+            "5 / 60".parse::<Quota>().unwrap(),
+            Quota {
+                requests: 5,
+                per: Duration::from_secs(60),
+            },
+        );
+        assert!("5".parse::<Quota>().is_err());
+    }
+
+    #[test]
+    fn user_limit() {
+        let limiter = limiter();
+        let now = Instant::now();
+        assert_eq!(limiter.check_at(Resource::Sandbox, user(1), now), Ok(()));
+        assert_eq!(
+            limiter.check_at(Resource::Sandbox, user(1), now + SECOND),
+            Ok(()),
+        );
+        assert_eq!(
+            limiter.check_at(Resource::Sandbox, user(1), now + 2 * SECOND),
+            Err(RateLimited {
+                scope: Scope::User,
+                retry_after: 8 * SECOND,
+            }),
+        );
+        assert_eq!(
+            limiter.check_at(Resource::Translation, user(1), now + 2 * SECOND),
+            Ok(()),
+        );
+        assert_eq!(
+            limiter.check_at(Resource::AutoTranslation, user(1), now + 2 * SECOND),
+            Ok(()),
+        );
+        assert_eq!(
+            limiter.check_at(Resource::Sandbox, user(1), now + 10 * SECOND),
+            Ok(()),
+        );
+    }
+
+    #[test]
+    fn channel_limit() {
+        let limiter = limiter();
+        let now = Instant::now();
+        for id in 1..=3 {
+            assert_eq!(limiter.check_at(Resource::Sandbox, user(id), now), Ok(()));
+        }
+        assert_eq!(
+            limiter.check_at(Resource::Sandbox, user(4), now),
+            Err(RateLimited {
+                scope: Scope::Channel,
+                retry_after: 10 * SECOND,
+            }),
+        );
+    }
+
+    #[test]
+    fn prunes_expired_buckets() {
+        let limiter = limiter();
+        let now = Instant::now();
+        for id in 1..=2 {
+            assert_eq!(limiter.check_at(Resource::Sandbox, user(id), now), Ok(()));
+        }
+        assert_eq!(limiter.requests.lock().unwrap().len(), 4);
+        assert_eq!(
+            limiter.check_at(Resource::Translation, user(3), now + 10 * SECOND),
+            Ok(()),
+        );
+        assert_eq!(limiter.requests.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn message() {
+        let rate_limited = RateLimited {
+            scope: Scope::Guild,
+            retry_after: Duration::from_millis(2500),
+        };
+        assert_eq!(
+            rate_limited.to_string(),
+            "Slow down! This server is sending too many requests, try again in 3 seconds.",
+        );
+    }
+}
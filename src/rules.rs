@@ -0,0 +1,240 @@
+// SPDX-FileCopyrightText: 2023 Konrad Borowski <konrad@borowski.pw>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::storage::CommandRule;
+use crate::Context;
+use anyhow::Result;
+use poise::command;
+use serenity::model::channel::{Channel, ChannelType};
+use serenity::model::id::ChannelId;
+
+/// Command managing rules, which cannot be disabled to avoid locking admins out.
+const RULES_COMMAND: &str = "commands";
+
+/// Name of the top-level command, rules for it apply to its subcommands too.
+fn root_name(ctx: Context<'_>) -> &str {
+    let root = ctx.parent_commands().first().copied();
+    &root.unwrap_or(ctx.command()).name
+}
+
+/// Checks whether the channel is a thread inside of another channel.
+pub fn is_thread(channel: &Channel) -> bool {
+    matches!(
+        channel,
+        Channel::Guild(channel) if matches!(
+            channel.kind,
+            ChannelType::PublicThread | ChannelType::PrivateThread | ChannelType::NewsThread
+        ),
+    )
+}
+
+/// Channel whose rules apply, threads follow rules of their parent channel.
+fn rules_channel(channel: &Channel) -> ChannelId {
+    match channel {
+        Channel::Guild(thread) if is_thread(channel) => thread.parent_id.unwrap_or(thread.id),
+        _ => channel.id(),
+    }
+}
+
+/// Refuses commands disabled in the channel or server they were invoked in.
+pub async fn check(ctx: Context<'_>) -> Result<bool> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return Ok(true);
+    };
+    let name = root_name(ctx);
+    if name == RULES_COMMAND {
+        return Ok(true);
+    }
+    let storage = &ctx.data().storage;
+    // Avoids fetching the channel for commands without rules
+    let rules = storage.command_rules(guild_id.0)?;
+    if rules.iter().all(|rule| rule.command != name) {
+        return Ok(true);
+    }
+    let channel = ctx.channel_id().to_channel(ctx).await?;
+    storage.command_enabled(guild_id.0, rules_channel(&channel).0, name)
+}
+
+/// Enable or disable commands in this server or its channels.
+///
+/// Channel rules take priority over server rules, so a command can be \
+/// disabled in the server and enabled only in a bot channel. Threads \
+/// follow rules of their parent channel. Context menu commands are \
+/// referred to by their displayed names.
+///
+/// Examples:
+/// `!xb commands disable pyeval`
+/// `!xb commands enable pyeval #bot-spam`
+/// `!xb commands reset pyeval #bot-spam`
+/// `!xb commands disable "Translate to English"`
+/// `!xb commands list`
+#[command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    subcommands(
+        "commands_enable",
+        "commands_disable",
+        "commands_reset",
+        "commands_list"
+    )
+)]
+pub async fn commands(ctx: Context<'_>) -> Result<()> {
+    list(ctx).await
+}
+
+async fn set_rule(
+    ctx: Context<'_>,
+    name: String,
+    channel: Option<Channel>,
+    enabled: Option<bool>,
+) -> Result<()> {
+    // Context menu commands can be referred to by their displayed names
+    let command = ctx
+        .framework()
+        .options()
+        .commands
+        .iter()
+        .find(|command| command.name == name || command.context_menu_name == Some(&*name));
+    let Some(command) = command else {
+        ctx.say(format!("There is no command {name}.")).await?;
+        return Ok(());
+    };
+    let name = &command.name;
+    if name == RULES_COMMAND {
+        ctx.say(format!("Command {name} cannot be disabled."))
+            .await?;
+        return Ok(());
+    }
+    let guild_id = ctx.guild_id().map_or(0, |id| id.0);
+    let channel_id = channel.as_ref().map(|channel| rules_channel(channel).0);
+    ctx.data()
+        .storage
+        .set_command_rule(guild_id, channel_id, name, enabled)?;
+    let place = match channel_id {
+        Some(channel_id) => format!("<#{channel_id}>"),
+        None => "this server".into(),
+    };
+    let message = match enabled {
+        Some(true) => format!("Enabled {name} in {place}."),
+        Some(false) => format!("Disabled {name} in {place}."),
+        None => format!("Removed rule for {name} in {place}."),
+    };
+    ctx.say(message).await?;
+    Ok(())
+}
+
+/// Enable a command.
+#[command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    rename = "enable"
+)]
+async fn commands_enable(
+    ctx: Context<'_>,
+    #[description = "Command name"] command: String,
+    #[description = "Channel, entire server when not provided"] channel: Option<Channel>,
+) -> Result<()> {
+    set_rule(ctx, command, channel, Some(true)).await
+}
+
+/// Disable a command.
+#[command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    rename = "disable"
+)]
+async fn commands_disable(
+    ctx: Context<'_>,
+    #[description = "Command name"] command: String,
+    #[description = "Channel, entire server when not provided"] channel: Option<Channel>,
+) -> Result<()> {
+    set_rule(ctx, command, channel, Some(false)).await
+}
+
+/// Remove a rule for a command.
+#[command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    rename = "reset"
+)]
+async fn commands_reset(
+    ctx: Context<'_>,
+    #[description = "Command name"] command: String,
+    #[description = "Channel, entire server when not provided"] channel: Option<Channel>,
+) -> Result<()> {
+    set_rule(ctx, command, channel, None).await
+}
+
+/// List command rules of this server.
+#[command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    rename = "list"
+)]
+async fn commands_list(ctx: Context<'_>) -> Result<()> {
+    list(ctx).await
+}
+
+async fn list(ctx: Context<'_>) -> Result<()> {
+    let guild_id = ctx.guild_id().map_or(0, |id| id.0);
+    let rules = ctx.data().storage.command_rules(guild_id)?;
+    if rules.is_empty() {
+        ctx.say("All commands are enabled.").await?;
+        return Ok(());
+    }
+    let lines: Vec<_> = rules
+        .iter()
+        .map(
+            |CommandRule {
+                 channel_id,
+                 command,
+                 enabled,
+             }| {
+                let state = if *enabled { "enabled" } else { "disabled" };
+                match channel_id {
+                    Some(channel_id) => format!("`{command}` {state} in <#{channel_id}>"),
+                    None => format!("`{command}` {state} in this server"),
+                }
+            },
+        )
+        .collect();
+    ctx.say(lines.join("\n")).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::rules_channel;
+    use serde_json::json;
+    use serenity::model::channel::Channel;
+    use serenity::model::id::ChannelId;
+
+    fn channel(kind: u8) -> Channel {
+        serde_json::from_value(json!({
+            "id": "3",
+            "type": kind,
+            "guild_id": "1",
+            "parent_id": "2",
+            "name": "channel",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn threads_follow_parent_rules() {
+        assert_eq!(rules_channel(&channel(0)), ChannelId(3));
+        assert_eq!(rules_channel(&channel(11)), ChannelId(2));
+        assert_eq!(rules_channel(&channel(12)), ChannelId(2));
+    }
+}
@@ -0,0 +1,102 @@
+// SPDX-FileCopyrightText: 2023 Konrad Borowski <konrad@borowski.pw>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::Context;
+use anyhow::Result;
+use poise::command;
+
+/// Save and show code snippets.
+///
+/// Snippets are saved per server, only their authors can replace or delete them.
+///
+/// Examples:
+/// `!xb snippet save hello ```rust fn main() { println!("Hello"); } ````
+/// `!xb snippet show hello`
+/// `!xb snippet list`
+/// `!xb snippet delete hello`
+#[command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    subcommands("snippet_save", "snippet_show", "snippet_list", "snippet_delete")
+)]
+pub async fn snippet(ctx: Context<'_>) -> Result<()> {
+    snippet_list_inner(ctx).await
+}
+
+fn guild_id(ctx: Context<'_>) -> u64 {
+    ctx.guild_id().map_or(0, |id| id.0)
+}
+
+/// Save a code snippet.
+#[command(prefix_command, slash_command, guild_only, rename = "save")]
+async fn snippet_save(
+    ctx: Context<'_>,
+    #[description = "Snippet name"] name: String,
+    #[description = "Snippet contents"]
+    #[rest]
+    content: String,
+) -> Result<()> {
+    let storage = &ctx.data().storage;
+    if let Some(snippet) = storage.snippet(guild_id(ctx), &name)? {
+        if snippet.author_id != ctx.author().id.0 {
+            ctx.say(format!("Snippet {name} belongs to someone else."))
+                .await?;
+            return Ok(());
+        }
+    }
+    storage.save_snippet(guild_id(ctx), &name, ctx.author().id.0, &content)?;
+    ctx.say(format!("Saved snippet {name}.")).await?;
+    Ok(())
+}
+
+/// Show a code snippet.
+#[command(prefix_command, slash_command, guild_only, rename = "show")]
+async fn snippet_show(
+    ctx: Context<'_>,
+    #[description = "Snippet name"] name: String,
+) -> Result<()> {
+    match ctx.data().storage.snippet(guild_id(ctx), &name)? {
+        Some(snippet) => ctx.say(snippet.content).await?,
+        None => ctx.say(format!("There is no snippet {name}.")).await?,
+    };
+    Ok(())
+}
+
+/// List code snippets.
+#[command(prefix_command, slash_command, guild_only, rename = "list")]
+async fn snippet_list(ctx: Context<'_>) -> Result<()> {
+    snippet_list_inner(ctx).await
+}
+
+async fn snippet_list_inner(ctx: Context<'_>) -> Result<()> {
+    let names = ctx.data().storage.snippet_names(guild_id(ctx))?;
+    if names.is_empty() {
+        ctx.say("There are no snippets.").await?;
+    } else {
+        ctx.say(format!("Snippets: {}", names.join(", "))).await?;
+    }
+    Ok(())
+}
+
+/// Delete a code snippet.
+#[command(prefix_command, slash_command, guild_only, rename = "delete")]
+async fn snippet_delete(
+    ctx: Context<'_>,
+    #[description = "Snippet name"] name: String,
+) -> Result<()> {
+    let storage = &ctx.data().storage;
+    let message = match storage.snippet(guild_id(ctx), &name)? {
+        None => format!("There is no snippet {name}."),
+        Some(snippet) if snippet.author_id != ctx.author().id.0 => {
+            format!("Snippet {name} belongs to someone else.")
+        }
+        Some(_) => {
+            storage.delete_snippet(guild_id(ctx), &name)?;
+            format!("Deleted snippet {name}.")
+        }
+    };
+    ctx.say(message).await?;
+    Ok(())
+}
@@ -0,0 +1,32 @@
+// SPDX-FileCopyrightText: 2023 Konrad Borowski <konrad@borowski.pw>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::Context;
+use anyhow::Result;
+use log::error;
+use poise::command;
+
+/// Counts command uses, called after every successful command.
+pub async fn record(ctx: Context<'_>) {
+    let command = &ctx.command().qualified_name;
+    if let Err(e) = ctx.data().storage.record_command(command) {
+        error!("Cannot record use of {command}: {e}");
+    }
+}
+
+/// Show most used commands.
+#[command(prefix_command, slash_command, owners_only, hide_in_help)]
+pub async fn stats(ctx: Context<'_>) -> Result<()> {
+    let usage = ctx.data().storage.command_usage(20)?;
+    if usage.is_empty() {
+        ctx.say("No commands were used yet.").await?;
+        return Ok(());
+    }
+    let lines: Vec<_> = usage
+        .iter()
+        .map(|(command, count)| format!("`{command}`: {count}"))
+        .collect();
+    ctx.say(lines.join("\n")).await?;
+    Ok(())
+}
@@ -0,0 +1,419 @@
+// SPDX-FileCopyrightText: 2023 Konrad Borowski <konrad@borowski.pw>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::autotranslate::AutoTranslate;
+use anyhow::{anyhow, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Migrations applied in order, `PRAGMA user_version` stores how many were applied.
+const MIGRATIONS: &[&str] = &[
+    include_str!("../migrations/0001_initial.sql"),
+    include_str!("../migrations/0002_guild_prefix.sql"),
+    include_str!("../migrations/0003_command_rules.sql"),
+    include_str!("../migrations/0004_prefix_replaces_default.sql"),
+];
+
+/// Command prefix chosen by a guild.
+#[derive(Debug, PartialEq, Eq)]
+pub struct GuildPrefix {
+    pub prefix: String,
+    /// Whether the default prefixes stop working in the guild.
+    pub replaces_default: bool,
+}
+
+/// Enables or disables a command in a guild or in one of its channels.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CommandRule {
+    /// `None` for rules applying to the entire guild.
+    pub channel_id: Option<u64>,
+    pub command: String,
+    pub enabled: bool,
+}
+
+pub struct Snippet {
+    pub author_id: u64,
+    pub content: String,
+}
+
+/// SQLite database storing bot state surviving restarts.
+pub struct Storage {
+    connection: Mutex<Connection>,
+}
+
+impl Storage {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::new(Connection::open(path)?)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Self> {
+        Self::new(Connection::open_in_memory()?)
+    }
+
+    fn new(mut connection: Connection) -> Result<Self> {
+        migrate(&mut connection)?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    pub fn guild_translator(&self, guild_id: u64) -> Result<Option<String>> {
+        let translator = self
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT translator FROM guild_settings WHERE guild_id = ?",
+                [guild_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(translator.flatten())
+    }
+
+    pub fn set_guild_translator(&self, guild_id: u64, translator: Option<&str>) -> Result<()> {
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO guild_settings (guild_id, translator) VALUES (?1, ?2)
+            ON CONFLICT (guild_id) DO UPDATE SET translator = ?2",
+            params![guild_id, translator],
+        )?;
+        Ok(())
+    }
+
+    pub fn guild_prefix(&self, guild_id: u64) -> Result<Option<GuildPrefix>> {
+        let prefix = self
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT prefix, prefix_replaces_default FROM guild_settings
+                WHERE guild_id = ? AND prefix IS NOT NULL",
+                [guild_id],
+                |row| {
+                    Ok(GuildPrefix {
+                        prefix: row.get(0)?,
+                        replaces_default: row.get(1)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(prefix)
+    }
+
+    pub fn set_guild_prefix(&self, guild_id: u64, prefix: Option<&GuildPrefix>) -> Result<()> {
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO guild_settings (guild_id, prefix, prefix_replaces_default)
+            VALUES (?1, ?2, ?3)
+            ON CONFLICT (guild_id) DO UPDATE SET prefix = ?2, prefix_replaces_default = ?3",
+            params![
+                guild_id,
+                prefix.map(|prefix| &prefix.prefix),
+                prefix.is_some_and(|prefix| prefix.replaces_default),
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn auto_translate(&self, channel_id: u64) -> Result<Option<AutoTranslate>> {
+        let settings: Option<(String, String)> = self
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT mode, skip FROM auto_translate WHERE channel_id = ?",
+                [channel_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        settings
+            .map(|(mode, skip)| {
+                Ok(AutoTranslate {
+                    mode: mode
+                        .parse()
+                        .map_err(|_| anyhow!("Invalid automatic translation mode {mode}"))?,
+                    skip: skip.split(',').map(String::from).collect(),
+                })
+            })
+            .transpose()
+    }
+
+    pub fn set_auto_translate(
+        &self,
+        channel_id: u64,
+        settings: Option<&AutoTranslate>,
+    ) -> Result<()> {
+        let connection = self.connection.lock().unwrap();
+        match settings {
+            Some(AutoTranslate { mode, skip }) => connection.execute(
+                "INSERT OR REPLACE INTO auto_translate (channel_id, mode, skip) VALUES (?, ?, ?)",
+                params![channel_id, mode.name(), skip.join(",")],
+            )?,
+            None => connection.execute(
+                "DELETE FROM auto_translate WHERE channel_id = ?",
+                [channel_id],
+            )?,
+        };
+        Ok(())
+    }
+
+    /// Checks whether command is enabled, channel rules take priority over guild rules.
+    pub fn command_enabled(&self, guild_id: u64, channel_id: u64, command: &str) -> Result<bool> {
+        let enabled = self
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT enabled FROM command_rules
+                WHERE guild_id = ? AND channel_id IN (0, ?) AND command = ?
+                ORDER BY channel_id DESC LIMIT 1",
+                params![guild_id, channel_id, command],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(enabled.unwrap_or(true))
+    }
+
+    /// Sets a command rule, removing it when `enabled` is `None`.
+    pub fn set_command_rule(
+        &self,
+        guild_id: u64,
+        channel_id: Option<u64>,
+        command: &str,
+        enabled: Option<bool>,
+    ) -> Result<()> {
+        let connection = self.connection.lock().unwrap();
+        let channel_id = channel_id.unwrap_or(0);
+        match enabled {
+            Some(enabled) => connection.execute(
+                "INSERT OR REPLACE INTO command_rules (guild_id, channel_id, command, enabled)
+                VALUES (?, ?, ?, ?)",
+                params![guild_id, channel_id, command, enabled],
+            )?,
+            None => connection.execute(
+                "DELETE FROM command_rules WHERE guild_id = ? AND channel_id = ? AND command = ?",
+                params![guild_id, channel_id, command],
+            )?,
+        };
+        Ok(())
+    }
+
+    pub fn command_rules(&self, guild_id: u64) -> Result<Vec<CommandRule>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(
+            "SELECT channel_id, command, enabled FROM command_rules
+            WHERE guild_id = ? ORDER BY command, channel_id",
+        )?;
+        let rows = statement.query_map([guild_id], |row| {
+            let channel_id: u64 = row.get(0)?;
+            Ok(CommandRule {
+                channel_id: (channel_id != 0).then_some(channel_id),
+                command: row.get(1)?,
+                enabled: row.get(2)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    pub fn record_command(&self, command: &str) -> Result<()> {
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO command_usage (command, count) VALUES (?, 1)
+            ON CONFLICT (command) DO UPDATE SET count = count + 1",
+            [command],
+        )?;
+        Ok(())
+    }
+
+    /// Returns the most used commands with their use counts.
+    pub fn command_usage(&self, limit: u32) -> Result<Vec<(String, u64)>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection
+            .prepare("SELECT command, count FROM command_usage ORDER BY count DESC LIMIT ?")?;
+        let rows = statement.query_map([limit], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    pub fn snippet(&self, guild_id: u64, name: &str) -> Result<Option<Snippet>> {
+        Ok(self
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT author_id, content FROM snippets WHERE guild_id = ? AND name = ?",
+                params![guild_id, name],
+                |row| {
+                    Ok(Snippet {
+                        author_id: row.get(0)?,
+                        content: row.get(1)?,
+                    })
+                },
+            )
+            .optional()?)
+    }
+
+    pub fn snippet_names(&self, guild_id: u64) -> Result<Vec<String>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement =
+            connection.prepare("SELECT name FROM snippets WHERE guild_id = ? ORDER BY name")?;
+        let rows = statement.query_map([guild_id], |row| row.get(0))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    pub fn save_snippet(
+        &self,
+        guild_id: u64,
+        name: &str,
+        author_id: u64,
+        content: &str,
+    ) -> Result<()> {
+        self.connection.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO snippets (guild_id, name, author_id, content)
+            VALUES (?, ?, ?, ?)",
+            params![guild_id, name, author_id, content],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_snippet(&self, guild_id: u64, name: &str) -> Result<()> {
+        self.connection.lock().unwrap().execute(
+            "DELETE FROM snippets WHERE guild_id = ? AND name = ?",
+            params![guild_id, name],
+        )?;
+        Ok(())
+    }
+
+    /// Returns a cached value that hasn't expired yet.
+    pub fn cached(&self, key: &str) -> Result<Option<String>> {
+        Ok(self
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT value FROM cache WHERE key = ? AND expires_at > ?",
+                params![key, unix_time(SystemTime::now())],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    pub fn cache(&self, key: &str, value: &str, ttl: Duration) -> Result<()> {
+        self.connection.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO cache (key, value, expires_at) VALUES (?, ?, ?)",
+            params![key, value, unix_time(SystemTime::now() + ttl)],
+        )?;
+        Ok(())
+    }
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+fn migrate(connection: &mut Connection) -> Result<()> {
+    let version: usize = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (applied, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", applied + 1)?;
+        transaction.commit()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CommandRule, GuildPrefix, Storage};
+    use crate::autotranslate::{AutoTranslate, Mode};
+    use std::time::Duration;
+
+    #[test]
+    fn settings() {
+        let storage = Storage::open_in_memory().unwrap();
+        assert_eq!(storage.guild_translator(1).unwrap(), None);
+        storage
+            .set_guild_translator(1, Some("libretranslate"))
+            .unwrap();
+        assert_eq!(
+            storage.guild_translator(1).unwrap().as_deref(),
+            Some("libretranslate"),
+        );
+        let settings = AutoTranslate {
+            mode: Mode::Thread,
+            skip: vec!["EN".into(), "PL".into()],
+        };
+        storage.set_auto_translate(2, Some(&settings)).unwrap();
+        let stored = storage.auto_translate(2).unwrap().unwrap();
+        assert_eq!(stored.mode, Mode::Thread);
+        assert_eq!(stored.skip, ["EN", "PL"]);
+        storage.set_auto_translate(2, None).unwrap();
+        assert!(storage.auto_translate(2).unwrap().is_none());
+        assert_eq!(storage.guild_prefix(1).unwrap(), None);
+        let prefix = GuildPrefix {
+            prefix: "!".into(),
+            replaces_default: true,
+        };
+        storage.set_guild_prefix(1, Some(&prefix)).unwrap();
+        assert_eq!(storage.guild_prefix(1).unwrap(), Some(prefix));
+        storage.set_guild_prefix(1, None).unwrap();
+        assert_eq!(storage.guild_prefix(1).unwrap(), None);
+    }
+
+    #[test]
+    fn command_rules() {
+        let storage = Storage::open_in_memory().unwrap();
+        assert!(storage.command_enabled(1, 2, "pyeval").unwrap());
+        storage
+            .set_command_rule(1, None, "pyeval", Some(false))
+            .unwrap();
+        storage
+            .set_command_rule(1, Some(2), "pyeval", Some(true))
+            .unwrap();
+        assert!(storage.command_enabled(1, 2, "pyeval").unwrap());
+        assert!(!storage.command_enabled(1, 3, "pyeval").unwrap());
+        assert!(storage.command_enabled(4, 3, "pyeval").unwrap());
+        assert_eq!(
+            storage.command_rules(1).unwrap(),
+            [
+                CommandRule {
+                    channel_id: None,
+                    command: "pyeval".into(),
+                    enabled: false,
+                },
+                CommandRule {
+                    channel_id: Some(2),
+                    command: "pyeval".into(),
+                    enabled: true,
+                },
+            ],
+        );
+        storage
+            .set_command_rule(1, Some(2), "pyeval", None)
+            .unwrap();
+        assert!(!storage.command_enabled(1, 2, "pyeval").unwrap());
+    }
+
+    #[test]
+    fn command_usage() {
+        let storage = Storage::open_in_memory().unwrap();
+        for command in ["trans", "ceval", "trans"] {
+            storage.record_command(command).unwrap();
+        }
+        assert_eq!(
+            storage.command_usage(10).unwrap(),
+            [("trans".into(), 2), ("ceval".into(), 1)],
+        );
+    }
+
+    #[test]
+    fn cache() {
+        let storage = Storage::open_in_memory().unwrap();
+        storage.cache("a", "1", Duration::from_secs(60)).unwrap();
+        storage.cache("b", "2", Duration::ZERO).unwrap();
+        assert_eq!(storage.cached("a").unwrap().as_deref(), Some("1"));
+        assert_eq!(storage.cached("b").unwrap(), None);
+    }
+}
@@ -0,0 +1,149 @@
+// SPDX-FileCopyrightText: 2023 Konrad Borowski <konrad@borowski.pw>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+//! Test doubles for running commands without connecting to Discord.
+
+use crate::deepl::{DeepL, UsageTracker};
+use crate::language::Languages;
+use crate::ratelimit::{Limits, Origin, RateLimiter};
+use crate::storage::Storage;
+use crate::translator::Translators;
+use crate::transport::{Reply, Transport};
+use crate::Data;
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::Client;
+use serde_json::Value;
+use std::sync::Mutex;
+use tokio::sync::OnceCell;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// Data with no services to connect to, tests set URLs of mock servers
+/// they need.
+impl Default for Data {
+    fn default() -> Self {
+        Self {
+            sandbox_url: String::new(),
+            godbolt_url: String::new(),
+            languages: Languages::default(),
+            rate_limiter: RateLimiter::new(Limits::default()),
+            translators: Translators {
+                deepl: Some(DeepL::new(
+                    Client::new(),
+                    String::new(),
+                    String::new(),
+                    UsageTracker::new(0.9, 1.0),
+                )),
+                libretranslate: None,
+            },
+            storage: Storage::open_in_memory().unwrap(),
+            prefixes: vec!["!xb ".into()],
+            auto_translate: true,
+            godbolt_compilers: OnceCell::new(),
+            client: Client::new(),
+        }
+    }
+}
+
+/// Starts a sandbox responding to every request with `response`.
+pub async fn mock_sandbox(response: Value) -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(response))
+        .mount(&server)
+        .await;
+    server
+}
+
+/// Returns JSON bodies of requests received by a mock server.
+pub async fn received_json(server: &MockServer) -> Vec<Value> {
+    server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| request.body_json().unwrap())
+        .collect()
+}
+
+/// Transport recording replies instead of posting them.
+pub struct FakeTransport {
+    data: Data,
+    files: Vec<(String, String)>,
+    replies: Mutex<Vec<Reply>>,
+}
+
+impl FakeTransport {
+    pub fn new(data: Data) -> Self {
+        Self {
+            data,
+            files: Vec::new(),
+            replies: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Transport with data using `sandbox` as the sandbox.
+    pub fn with_sandbox(sandbox: &MockServer) -> Self {
+        Self::new(Data {
+            sandbox_url: sandbox.uri(),
+            ..Data::default()
+        })
+    }
+
+    pub fn with_files(mut self, files: &[(&str, &str)]) -> Self {
+        self.files = files
+            .iter()
+            .map(|&(name, contents)| (name.into(), contents.into()))
+            .collect();
+        self
+    }
+
+    /// Returns replies posted so far, with edits applied.
+    pub fn replies(&self) -> Vec<Reply> {
+        self.replies.lock().unwrap().clone()
+    }
+}
+
+impl Default for FakeTransport {
+    fn default() -> Self {
+        Self::new(Data::default())
+    }
+}
+
+#[async_trait]
+impl Transport for FakeTransport {
+    type Message = usize;
+
+    fn data(&self) -> &Data {
+        &self.data
+    }
+
+    fn origin(&self) -> Origin {
+        Origin::default()
+    }
+
+    async fn send(&self, reply: Reply) -> Result<usize> {
+        let mut replies = self.replies.lock().unwrap();
+        replies.push(reply);
+        Ok(replies.len() - 1)
+    }
+
+    async fn edit(&self, message: &usize, reply: Reply) -> Result<()> {
+        self.replies.lock().unwrap()[*message] = reply;
+        Ok(())
+    }
+
+    async fn attached_files(
+        &self,
+        accept: for<'n> fn(&'n str) -> bool,
+    ) -> Result<Vec<(String, String)>> {
+        Ok(self
+            .files
+            .iter()
+            .filter(|(name, _)| accept(name))
+            .cloned()
+            .collect())
+    }
+}
@@ -0,0 +1,319 @@
+// SPDX-FileCopyrightText: 2023 Konrad Borowski <konrad@borowski.pw>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::Context;
+use anyhow::{bail, Result};
+use poise::command;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Toolchain setting selectable with `name=value` options, like a compiler
+/// or a language standard.
+pub(crate) struct Setting {
+    pub(crate) name: &'static str,
+    /// Pairs of values users can pick and command fragments they select.
+    pub(crate) values: &'static [(&'static str, &'static str)],
+    pub(crate) default: &'static str,
+}
+
+const CPP: &[Setting] = &[
+    Setting {
+        name: "compiler",
+        values: &[("clang", "clang++"), ("gcc", "g++")],
+        default: "clang",
+    },
+    Setting {
+        name: "std",
+        values: &[
+            ("c++11", "-std=c++11"),
+            ("c++14", "-std=c++14"),
+            ("c++17", "-std=c++17"),
+            ("c++20", "-std=c++20"),
+            ("c++23", "-std=c++2b"),
+        ],
+        default: "c++17",
+    },
+];
+
+const RUST: &[Setting] = &[
+    Setting {
+        name: "channel",
+        values: &[
+            ("stable", "$RUST_STABLE"),
+            ("beta", "$RUST_BETA"),
+            ("nightly", "$RUST_NIGHTLY"),
+        ],
+        default: "nightly",
+    },
+    Setting {
+        name: "edition",
+        values: &[
+            ("2015", "--edition 2015"),
+            ("2018", "--edition 2018"),
+            ("2021", "--edition 2021"),
+        ],
+        default: "2021",
+    },
+];
+
+/// Options an evaluator accepts.
+#[derive(Clone, Copy)]
+pub(crate) struct Allowlist<'a> {
+    pub(crate) settings: &'a [Setting],
+    /// Compiler or interpreter flags, `*` at the end matches any suffix.
+    pub(crate) flags: &'a [&'a str],
+}
+
+pub(crate) const CPP_OPTIONS: Allowlist<'static> = Allowlist {
+    settings: CPP,
+    flags: &[
+        "-O0",
+        "-O1",
+        "-O2",
+        "-O3",
+        "-Os",
+        "-Og",
+        "-Ofast",
+        "-g",
+        "-D*",
+        "-U*",
+        "-Wall",
+        "-Wextra",
+        "-Wpedantic",
+        "-Wshadow",
+        "-Wconversion",
+        "-Werror",
+        "-Werror=*",
+        "-Wno-*",
+        "-pedantic",
+        "-pedantic-errors",
+        "-pthread",
+        "-ffast-math",
+        "-fno-exceptions",
+        "-fno-rtti",
+        "-fwrapv",
+        "-fsanitize=*",
+    ],
+};
+
+pub(crate) const RUST_OPTIONS: Allowlist<'static> = Allowlist {
+    settings: RUST,
+    flags: &[
+        "-O",
+        "-g",
+        "-Copt-level=*",
+        "-Cdebug-assertions=*",
+        "-Coverflow-checks=*",
+        "-Cpanic=*",
+        "--cfg=*",
+        "-A*",
+        "-W*",
+        "-D*",
+        "-F*",
+    ],
+};
+
+pub(crate) const PYTHON_OPTIONS: Allowlist<'static> = Allowlist {
+    settings: &[],
+    flags: &[
+        "-b", "-bb", "-B", "-E", "-I", "-O", "-OO", "-s", "-S", "-W*", "-X*",
+    ],
+};
+
+/// Built-in evaluators, listed by `toolchains` command.
+const EVALUATORS: &[(&str, Allowlist<'static>)] = &[
+    ("ceval", CPP_OPTIONS),
+    ("rusteval", RUST_OPTIONS),
+    ("pyeval", PYTHON_OPTIONS),
+];
+
+/// Command fragments selected by options, by setting name.
+pub(crate) type Selection = BTreeMap<&'static str, &'static str>;
+
+/// Options provided before the code, validated against an allowlist.
+#[derive(Debug)]
+pub(crate) struct EvalOptions {
+    pub(crate) toolchain: Selection,
+    /// Flags quoted for use in a shell command.
+    pub(crate) flags: String,
+}
+
+impl Allowlist<'_> {
+    /// Parses `name=value` toolchain settings and allowed flags.
+    pub(crate) fn parse(self, options: &str) -> Result<EvalOptions> {
+        let mut toolchain = Selection::new();
+        let mut flags = Vec::new();
+        for option in options.split_whitespace() {
+            let setting = option.split_once('=').and_then(|(name, value)| {
+                let setting = self.settings.iter().find(|setting| setting.name == name)?;
+                Some((setting, value))
+            });
+            let Some((setting, value)) = setting else {
+                if !self.allows(option) {
+                    bail!(
+                        "Option {option} is not allowed, allowed options: {}",
+                        self.describe(),
+                    );
+                }
+                flags.push(quote(option));
+                continue;
+            };
+            let Some(&(_, fragment)) = setting.values.iter().find(|(v, _)| *v == value) else {
+                bail!(
+                    "Unknown {} {value}, available: {}",
+                    setting.name,
+                    available(setting),
+                );
+            };
+            if toolchain.insert(setting.name, fragment).is_some() {
+                bail!("{} is provided multiple times", setting.name);
+            }
+        }
+        for setting in self.settings {
+            toolchain.entry(setting.name).or_insert_with(|| {
+                let (_, fragment) = setting
+                    .values
+                    .iter()
+                    .find(|(value, _)| *value == setting.default)
+                    .unwrap();
+                fragment
+            });
+        }
+        Ok(EvalOptions {
+            toolchain,
+            flags: flags.join(" "),
+        })
+    }
+
+    fn allows(self, flag: &str) -> bool {
+        self.flags
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => flag.len() > prefix.len() && flag.starts_with(prefix),
+                None => flag == *pattern,
+            })
+    }
+
+    fn describe(self) -> String {
+        let settings = self
+            .settings
+            .iter()
+            .map(|setting| format!("{}=", setting.name));
+        let flags = self.flags.iter().map(|flag| flag.to_string());
+        let options: Vec<_> = settings.chain(flags).collect();
+        if options.is_empty() {
+            "none".into()
+        } else {
+            options.join(" ")
+        }
+    }
+}
+
+/// Quotes a word for a shell command, leaving simple words unchanged.
+fn quote(word: &str) -> String {
+    if word
+        .bytes()
+        .all(|c| c.is_ascii_alphanumeric() || b"-_=+.,:/@%".contains(&c))
+    {
+        word.into()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+fn available(setting: &Setting) -> String {
+    let values: Vec<_> = setting.values.iter().map(|(value, _)| *value).collect();
+    values.join(", ")
+}
+
+fn describe(evaluators: &[(&str, Allowlist<'_>)]) -> String {
+    let mut message = String::new();
+    for (command, allowlist) in evaluators {
+        writeln!(message, "**{command}**").unwrap();
+        for setting in allowlist.settings {
+            writeln!(
+                message,
+                "`{}=`: {} (default {})",
+                setting.name,
+                available(setting),
+                setting.default,
+            )
+            .unwrap();
+        }
+        if !allowlist.flags.is_empty() {
+            writeln!(message, "Flags: `{}`", allowlist.flags.join(" ")).unwrap();
+        }
+    }
+    message
+}
+
+/// List toolchains and options available to code evaluators.
+///
+/// Toolchains and flags are selected with options before the code, for \
+/// instance `!xb ceval compiler=gcc std=c++20 -O2 ```c++ ...````. \
+/// Flags ending with `*` accept any suffix, like `-Wno-unused`.
+#[command(prefix_command, slash_command)]
+pub async fn toolchains(ctx: Context<'_>) -> Result<()> {
+    let registry: Vec<_> = ctx.data().languages.flags().collect();
+    let mut evaluators = EVALUATORS.to_vec();
+    evaluators.extend(registry.iter().map(|(command, flags)| {
+        let allowlist = Allowlist {
+            settings: &[],
+            flags,
+        };
+        (*command, allowlist)
+    }));
+    ctx.say(describe(&evaluators)).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{describe, CPP_OPTIONS, EVALUATORS, PYTHON_OPTIONS, RUST_OPTIONS};
+
+    #[test]
+    fn selection() {
+        let options = CPP_OPTIONS.parse("-O2 compiler=gcc -Wall").unwrap();
+        assert_eq!(options.toolchain["compiler"], "g++");
+        assert_eq!(options.toolchain["std"], "-std=c++17");
+        assert_eq!(options.flags, "-O2 -Wall");
+        let options = RUST_OPTIONS.parse("edition=2018 channel=stable").unwrap();
+        assert_eq!(options.toolchain["channel"], "$RUST_STABLE");
+        assert_eq!(options.toolchain["edition"], "--edition 2018");
+        assert_eq!(options.flags, "");
+    }
+
+    #[test]
+    fn flags() {
+        let flags = |options| CPP_OPTIONS.parse(options).unwrap().flags;
+        assert_eq!(flags("-Wno-unused -DX=1"), "-Wno-unused -DX=1");
+        assert_eq!(flags("-DGREETING=\"it's\""), r#"'-DGREETING="it'\''s"'"#);
+        assert_eq!(PYTHON_OPTIONS.parse("-OO").unwrap().flags, "-OO");
+    }
+
+    #[test]
+    fn errors() {
+        let error = |options| CPP_OPTIONS.parse(options).err().unwrap().to_string();
+        assert_eq!(
+            error("compiler=msvc"),
+            "Unknown compiler msvc, available: clang, gcc",
+        );
+        assert_eq!(
+            error("std=c++20 std=c++17"),
+            "std is provided multiple times",
+        );
+        assert!(error("-Wall;id")
+            .starts_with("Option -Wall;id is not allowed, allowed options: compiler= std= -O0 "));
+        assert!(error("-Wno-").starts_with("Option -Wno- is not allowed"));
+        assert!(error("-fplugin=./x.so").starts_with("Option -fplugin=./x.so is not allowed"));
+        assert!(error("$(id)").starts_with("Option $(id) is not allowed"));
+    }
+
+    #[test]
+    fn description() {
+        let description = describe(EVALUATORS);
+        assert!(description.contains("`channel=`: stable, beta, nightly (default nightly)"));
+        assert!(description.contains("Flags: `-b -bb -B -E -I -O -OO -s -S -W* -X*`"));
+    }
+}
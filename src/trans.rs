@@ -2,10 +2,18 @@
 //
 // SPDX-License-Identifier: AGPL-3.0-or-later
 
+use crate::ratelimit::{self, Resource};
+use crate::translator::{
+    find_language, Formality, Language, Languages, Options, TagHandling, Translation,
+};
+use crate::transport::{Attachment, Reply, Transport};
 use crate::{Context, Data};
-use anyhow::{Error, Result};
-use poise::{command, Command};
-use serde::{Deserialize, Serialize};
+use anyhow::{anyhow, Error, Result};
+use poise::{command, AutocompleteChoice, Command};
+use serenity::model::channel::Message;
+
+/// Longest message Discord allows.
+const MAX_MESSAGE_LENGTH: usize = 2000;
 
 pub(crate) fn trans_merged() -> Command<Data, Error> {
     Command {
@@ -14,49 +22,117 @@ pub(crate) fn trans_merged() -> Command<Data, Error> {
     }
 }
 
-const SOURCE_LANGUAGES: &[&str] = &[
-    "BG", "CS", "DA", "DE", "EL", "EN", "ES", "ET", "FI", "FR", "HU", "ID", "IT", "JA", "LT", "LV",
-    "NL", "PL", "PT", "RO", "SK", "SL", "SV", "TR", "UK", "ZH",
-];
-const TARGET_LANGUAGES: &[&str] = &[
-    "BG", "CS", "DA", "DE", "EL", "EN", "EN-GB", "EN-US", "ES", "ET", "FI", "FR", "HU", "ID", "IT",
-    "JA", "LT", "LV", "NL", "PL", "PT", "PT-BR", "PT-PT", "RO", "RU", "SK", "SL", "SV", "TR", "UK",
-    "ZH",
-];
-
 #[command(prefix_command)]
 async fn trans_prefix(ctx: Context<'_>, #[rest] text: Option<String>) -> Result<()> {
-    let Some(text) = text else {
-        // Trans flag
-        ctx.say("\u{1F3F3}\u{FE0F}\u{200D}\u{26A7}\u{FE0F}").await?;
-        return Ok(());
+    let referenced = match ctx {
+        Context::Prefix(ctx) => ctx.msg.referenced_message.as_deref(),
+        Context::Application(_) => None,
     };
-    let mut text: &str = &text;
+    let mut text = text.as_deref().unwrap_or("");
+    let mut service = None;
     let mut from = None;
     let mut to = None;
-    if let Some((a, b)) = text.trim_start().split_once(char::is_whitespace) {
-        if let Some((source, target)) = a.split_once('-') {
+    let (mut first_word, mut rest) = split_first_word(text);
+    if let Some((name, spec)) = first_word.split_once(':') {
+        if ctx.data().translators.get(Some(name)).is_some() {
+            service = Some(name);
+            text = rest;
+            (first_word, rest) = if spec.is_empty() {
+                split_first_word(rest)
+            } else {
+                (spec, rest)
+            };
+        }
+    }
+    if let Some((source, target)) = first_word.split_once('-') {
+        // Without a replied to message a lone word is the text to translate
+        if !rest.is_empty() || referenced.is_some() {
             from = (!source.is_empty()).then_some(source);
             to = (!target.is_empty()).then_some(target);
-            text = b;
+            text = rest;
+        }
+    }
+    let (options, mut text) = parse_options(text)?;
+    let files = ctx.attached_files(is_document).await?;
+    if text.trim().is_empty() && files.is_empty() {
+        let Some(referenced) = referenced else {
+            // Trans flag
+            ctx.say("\u{1F3F3}\u{FE0F}\u{200D}\u{26A7}\u{FE0F}").await?;
+            return Ok(());
+        };
+        text = &referenced.content;
+    }
+    run_translation(ctx, service, from, to, &options, text, files).await
+}
+
+/// Checks whether an attachment is a text document that can be translated,
+/// other attachments like images are ignored.
+fn is_document(name: &str) -> bool {
+    let extension = name.rsplit_once('.').map_or("", |(_, extension)| extension);
+    ["txt", "htm", "html", "xlf", "xliff"]
+        .iter()
+        .any(|document| extension.eq_ignore_ascii_case(document))
+}
+
+/// Parses leading `key=value` options, values containing spaces can be quoted.
+fn parse_options(mut text: &str) -> Result<(Options, &str)> {
+    let mut options = Options::default();
+    while let Some((key, value)) = text.trim_start().split_once('=') {
+        let (value, rest) = match value.strip_prefix('"') {
+            Some(quoted) => quoted
+                .split_once('"')
+                .ok_or_else(|| anyhow!("Missing closing quote in {key} option"))?,
+            None => value.split_once(char::is_whitespace).unwrap_or((value, "")),
+        };
+        let invalid = || anyhow!("Invalid {key} option {value}");
+        match key {
+            "formality" => options.formality = Some(value.parse().map_err(|_| invalid())?),
+            "glossary" => options.glossary_id = Some(value.into()),
+            "preserve_formatting" => {
+                options.preserve_formatting = value.parse().map_err(|_| invalid())?;
+            }
+            "tag_handling" => options.tag_handling = Some(value.parse().map_err(|_| invalid())?),
+            "context" => options.context = Some(value.into()),
+            _ => break,
         }
+        text = rest;
     }
-    run_translation(ctx, from, to, text).await
+    Ok((options, text))
 }
 
-/// Translate text using DeepL.
+fn split_first_word(text: &str) -> (&str, &str) {
+    text.trim_start()
+        .split_once(char::is_whitespace)
+        .unwrap_or((text.trim(), ""))
+}
+
+/// Translate text using DeepL or LibreTranslate.
+///
+/// Translate text using DeepL or LibreTranslate. An optional source or target language \
+/// can be provided. When source is not provided, the service will try to guess the \
+/// language, when target is not provided, it will be assumed to be English. DeepL is \
+/// used unless another service is chosen by prefixing the languages with its name \
+/// or the server picked another default with the `translator` command.
 ///
-/// Translate text using DeepL. An optional source or target language can be provided. \
-/// When source is not provided, DeepL will try to guess the language, when target is \
-/// not provided, it will be assumed to be English.
+/// DeepL options can be provided before the text as `key=value`: `formality` \
+/// (`more`, `less`, `prefer_more`, `prefer_less`), `glossary` (glossary ID, \
+/// requires source language), `preserve_formatting` (`true` or `false`), \
+/// `tag_handling` (`xml` or `html`) and `context` (quoted text that helps \
+/// with translation, but is not translated).
 ///
 /// Examples:
 /// `!xb trans -fr Hello, world!`
 /// `!xb trans pl- Witaj świecie.`
 /// `!xb trans et-cs Tere, maailm!`
+/// `!xb trans libretranslate:es-en ¡Hola, mundo!`
+/// `!xb trans en-de formality=less How are you?`
 /// `!xb trans Ciao mondo!`
 /// `/trans こんにちは世界！`
+///
+/// When used as a reply without text, the replied to message is translated. \
+/// Attached text files are translated as documents using DeepL.
 #[command(prefix_command, track_edits, slash_command)]
+#[allow(clippy::too_many_arguments)]
 async fn trans(
     ctx: Context<'_>,
     #[description = "Source language"]
@@ -65,117 +141,481 @@ async fn trans(
     #[description = "Target language"]
     #[autocomplete = "target_language"]
     to: Option<String>,
+    #[description = "Translation service"]
+    #[autocomplete = "service"]
+    service: Option<String>,
+    #[description = "Formality of the translation"] formality: Option<Formality>,
+    #[description = "ID of glossary to use"] glossary: Option<String>,
+    #[description = "Keep formatting, like punctuation and capitalization"]
+    preserve_formatting: Option<bool>,
+    #[description = "Type of markup in the text"] tag_handling: Option<TagHandling>,
+    #[description = "Text that helps with translation, but is not translated"] context: Option<
+        String,
+    >,
     #[description = "Text to translate"]
     #[rest]
     text: String,
 ) -> Result<()> {
-    run_translation(ctx, from.as_deref(), to.as_deref(), &text).await
+    let options = Options {
+        formality,
+        glossary_id: glossary,
+        preserve_formatting: preserve_formatting.unwrap_or(false),
+        tag_handling,
+        context,
+    };
+    run_translation(
+        ctx,
+        service.as_deref(),
+        from.as_deref(),
+        to.as_deref(),
+        &options,
+        &text,
+        Vec::new(),
+    )
+    .await
+}
+
+/// Translate message to English.
+#[command(context_menu_command = "Translate to English", ephemeral)]
+pub async fn translate_message(ctx: Context<'_>, message: Message) -> Result<()> {
+    let options = Options::default();
+    run_translation(
+        ctx,
+        None,
+        None,
+        None,
+        &options,
+        &message.content,
+        Vec::new(),
+    )
+    .await
 }
 
 async fn source_language<'a>(
-    _ctx: Context<'a>,
+    ctx: Context<'a>,
     partial: &'a str,
-) -> impl Iterator<Item = String> + 'a {
-    autocomplete_case_insensitive(SOURCE_LANGUAGES, partial)
+) -> impl Iterator<Item = AutocompleteChoice<String>> + 'a {
+    let languages = all_languages(ctx, |languages| languages.source).await;
+    autocomplete_languages(languages, partial)
 }
 
 async fn target_language<'a>(
-    _ctx: Context<'a>,
+    ctx: Context<'a>,
     partial: &'a str,
-) -> impl Iterator<Item = String> + 'a {
-    autocomplete_case_insensitive(TARGET_LANGUAGES, partial)
+) -> impl Iterator<Item = AutocompleteChoice<String>> + 'a {
+    let languages = all_languages(ctx, |languages| languages.target).await;
+    autocomplete_languages(languages, partial)
 }
 
-fn autocomplete_case_insensitive<'a>(
-    list: &'a [&str],
-    partial: &'a str,
-) -> impl Iterator<Item = String> + 'a {
-    list.iter()
-        .filter(|elem| {
-            elem.get(..partial.len()).map_or(false, |trimmed_elem| {
-                trimmed_elem.eq_ignore_ascii_case(partial)
-            })
+async fn service<'a>(ctx: Context<'a>, partial: &'a str) -> impl Iterator<Item = String> + 'a {
+    ctx.data()
+        .translators
+        .names()
+        .filter(move |name| starts_with_case_insensitive(name, partial))
+        .map(String::from)
+}
+
+/// Languages supported by any of the translation services.
+async fn all_languages(
+    ctx: Context<'_>,
+    select: impl Fn(Languages) -> Vec<Language>,
+) -> Vec<Language> {
+    let mut all = Vec::new();
+    for translator in ctx.data().translators.iter() {
+        if let Ok(languages) = translator.languages().await {
+            all.extend(select(languages));
+        }
+    }
+    all.sort_by_key(|language| language.code.to_uppercase());
+    all.dedup_by(|a, b| a.code.eq_ignore_ascii_case(&b.code));
+    all
+}
+
+/// Suggests languages whose code or name starts with `partial`.
+fn autocomplete_languages(
+    list: Vec<Language>,
+    partial: &str,
+) -> impl Iterator<Item = AutocompleteChoice<String>> + '_ {
+    list.into_iter()
+        .filter(move |language| {
+            starts_with_case_insensitive(&language.code, partial)
+                || starts_with_case_insensitive(&language.name, partial)
+        })
+        .map(|Language { code, name }| AutocompleteChoice {
+            name: if name == code {
+                code.clone()
+            } else {
+                format!("{name} ({code})")
+            },
+            value: code,
         })
-        .map(|elem| String::from(*elem))
 }
 
-#[derive(Serialize)]
-struct TranslateRequest<'a> {
-    text: &'a str,
-    source_lang: Option<&'a str>,
-    target_lang: &'a str,
+fn starts_with_case_insensitive(elem: &str, partial: &str) -> bool {
+    elem.get(..partial.len())
+        .is_some_and(|trimmed_elem| trimmed_elem.eq_ignore_ascii_case(partial))
 }
 
-#[derive(Deserialize)]
-struct TranslateResponse {
-    translations: [Translation; 1],
+/// Show or change the default translation service of this server.
+#[command(
+    prefix_command,
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD"
+)]
+pub async fn translator(
+    ctx: Context<'_>,
+    #[description = "Translation service"]
+    #[autocomplete = "service"]
+    service: Option<String>,
+) -> Result<()> {
+    let data = ctx.data();
+    let guild_id = ctx.guild_id().map_or(0, |id| id.0);
+    let message = match service {
+        None => {
+            let current = data.storage.guild_translator(guild_id)?;
+            let name = data
+                .translators
+                .for_guild(current.as_deref())
+                .map_or("none", |translator| translator.name());
+            format!("This server uses {name} for translations.")
+        }
+        Some(service) => match data.translators.get(Some(&service)) {
+            Some(translator) => {
+                data.storage
+                    .set_guild_translator(guild_id, Some(translator.name()))?;
+                format!(
+                    "This server now uses {} for translations.",
+                    translator.name()
+                )
+            }
+            None => unknown_service(data, &service),
+        },
+    };
+    ctx.say(message).await?;
+    Ok(())
 }
 
-#[derive(Deserialize)]
-struct Translation {
-    text: String,
+fn unknown_service(data: &Data, service: &str) -> String {
+    format!(
+        "Unknown translation service {service}, available services are: {}.",
+        data.translators.names().collect::<Vec<_>>().join(", "),
+    )
+}
+
+/// Show DeepL character usage.
+#[command(prefix_command, slash_command, owners_only, hide_in_help)]
+pub async fn usage(ctx: Context<'_>) -> Result<()> {
+    let usage = ctx.data().translators.deepl()?.usage().await?;
+    ctx.say(format!(
+        "Used {} of {} DeepL characters ({:.1}%), {} remaining.",
+        usage.character_count,
+        usage.character_limit,
+        usage.used_fraction(0) * 100.0,
+        usage.character_limit.saturating_sub(usage.character_count),
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Manage DeepL glossaries.
+#[command(
+    prefix_command,
+    slash_command,
+    owners_only,
+    hide_in_help,
+    subcommands("glossary_create", "glossary_list")
+)]
+pub async fn glossary(ctx: Context<'_>) -> Result<()> {
+    glossary_list_inner(ctx).await
+}
+
+/// Create a DeepL glossary.
+///
+/// Entries are provided one per line in `source,target` CSV format.
+///
+/// Example:
+/// `!xb glossary create rust en-de`
+/// `borrow checker,Borrow-Checker`
+/// `crate,Crate`
+#[command(prefix_command, slash_command, owners_only, rename = "create")]
+async fn glossary_create(
+    ctx: Context<'_>,
+    #[description = "Glossary name"] name: String,
+    #[description = "Languages, like en-de"] languages: String,
+    #[description = "Entries in source,target CSV format"]
+    #[rest]
+    entries: String,
+) -> Result<()> {
+    let Some((source, target)) = languages.split_once('-') else {
+        ctx.say("Languages need to be provided as source-target, like en-de.")
+            .await?;
+        return Ok(());
+    };
+    let entries = entries
+        .trim()
+        .trim_start_matches("```csv")
+        .trim_matches('`')
+        .trim();
+    let glossary = ctx
+        .data()
+        .translators
+        .deepl()?
+        .create_glossary(&name, source, target, entries)
+        .await?;
+    ctx.say(format!(
+        "Created glossary {} with {} entries, use it with `glossary={}`.",
+        glossary.name, glossary.entry_count, glossary.glossary_id,
+    ))
+    .await?;
+    Ok(())
+}
+
+/// List DeepL glossaries.
+#[command(prefix_command, slash_command, owners_only, rename = "list")]
+async fn glossary_list(ctx: Context<'_>) -> Result<()> {
+    glossary_list_inner(ctx).await
+}
+
+async fn glossary_list_inner(ctx: Context<'_>) -> Result<()> {
+    let glossaries = ctx.data().translators.deepl()?.glossaries().await?;
+    if glossaries.is_empty() {
+        ctx.say("There are no glossaries.").await?;
+        return Ok(());
+    }
+    let list: Vec<_> = glossaries
+        .iter()
+        .map(|glossary| {
+            format!(
+                "`{}` {} ({} → {}, {} entries)",
+                glossary.glossary_id,
+                glossary.name,
+                glossary.source_lang,
+                glossary.target_lang,
+                glossary.entry_count,
+            )
+        })
+        .collect();
+    ctx.say(list.join("\n")).await?;
+    Ok(())
 }
 
 async fn run_translation(
     ctx: Context<'_>,
+    service: Option<&str>,
     from: Option<&str>,
     to: Option<&str>,
+    options: &Options,
     text: &str,
+    files: Vec<(String, String)>,
 ) -> Result<()> {
-    let api_key = &ctx.data().deepl_auth_key;
-    let source_lang = match from {
-        Some(lang) => {
-            let lang = lang.to_uppercase();
-            if !SOURCE_LANGUAGES.contains(&lang.as_str()) {
-                ctx.say(format!(
-                    concat!(
-                        "Unrecognized source language {lang}, ",
-                        "please refer to list of supported languages at ",
-                        "https://www.deepl.com/docs-api/translate-text/."
-                    ),
-                    lang = lang,
-                ))
-                .await?;
+    if text.trim().is_empty() && files.is_empty() {
+        ctx.say("There is no text to translate.").await?;
+        return Ok(());
+    }
+    if !text.trim().is_empty() && !files.is_empty() {
+        ctx.say("Translate either text or attached documents, not both.")
+            .await?;
+        return Ok(());
+    }
+    let guild_translator = match ctx.guild_id() {
+        Some(guild_id) => ctx.data().storage.guild_translator(guild_id.0)?,
+        None => None,
+    };
+    let translators = &ctx.data().translators;
+    let translator = match service {
+        Some(service) => translators.get(Some(service)),
+        None => translators.for_guild(guild_translator.as_deref()),
+    };
+    let Some(translator) = translator else {
+        ctx.say(unknown_service(ctx.data(), service.unwrap_or_default()))
+            .await?;
+        return Ok(());
+    };
+    let languages = translator.languages().await?;
+    let source = match from {
+        Some(lang) => match find_language(&languages.source, lang) {
+            Some(lang) => Some(lang),
+            None => {
+                ctx.say(unrecognized_language("source", lang, &languages.source))
+                    .await?;
                 return Ok(());
             }
-            Some(lang)
-        }
+        },
         None => None,
     };
-    let uppercase_target;
-    let target_lang = match to {
-        None => "EN-US",
-        Some(to) => {
-            uppercase_target = to.to_uppercase();
-            if !TARGET_LANGUAGES.contains(&uppercase_target.as_str()) {
-                ctx.say(format!(
-                    concat!(
-                        "Unrecognized target language {lang}, ",
-                        "please refer to list of supported languages at ",
-                        "https://www.deepl.com/docs-api/translate-text/."
-                    ),
-                    lang = uppercase_target,
-                ))
-                .await?;
+    let target = match to {
+        Some(lang) => match find_language(&languages.target, lang) {
+            Some(lang) => lang,
+            None => {
+                ctx.say(unrecognized_language("target", lang, &languages.target))
+                    .await?;
                 return Ok(());
             }
-            &uppercase_target
+        },
+        None => translator.default_target(),
+    };
+    if !ratelimit::allow(&ctx, Resource::Translation).await? {
+        return Ok(());
+    }
+    let reply = if files.is_empty() {
+        let translation = translator.translate(text, source, target, options).await?;
+        translation_reply(translation, source, target, &languages)
+    } else {
+        let mut attachments = Vec::new();
+        let mut notices = Vec::new();
+        for (name, contents) in files {
+            let translation = translator
+                .translate_document(&name, contents, source, target, options)
+                .await?;
+            notices.extend(translation.notice);
+            attachments.push(Attachment {
+                filename: name,
+                data: translation.text.into_bytes(),
+            });
+        }
+        Reply {
+            content: footer(None, source, target, &languages, notices.last()),
+            attachments,
         }
     };
-    let response: TranslateResponse = ctx
-        .data()
-        .client
-        .post("https://api-free.deepl.com/v2/translate")
-        .header("Authorization", format!("DeepL-Auth-Key {api_key}"))
-        .form(&TranslateRequest {
-            text,
-            source_lang: source_lang.as_deref(),
-            target_lang,
-        })
-        .send()
-        .await?
-        .json()
-        .await?;
-    ctx.say(&response.translations[0].text).await?;
+    Transport::send(&ctx, reply).await?;
     Ok(())
 }
+
+/// Creates a reply containing a translation followed by languages it was translated
+/// from and to, attaching the translation as a file when it doesn't fit in a message.
+pub(crate) fn translation_reply(
+    translation: Translation,
+    source: Option<&str>,
+    target: &str,
+    languages: &Languages,
+) -> Reply {
+    let footer = footer(
+        translation.detected_source.as_deref(),
+        source,
+        target,
+        languages,
+        translation.notice.as_ref(),
+    );
+    let content = format!("{}\n\n{footer}", translation.text);
+    if content.chars().count() <= MAX_MESSAGE_LENGTH {
+        return Reply::text(content);
+    }
+    Reply {
+        content: footer,
+        attachments: vec![Attachment {
+            filename: "translation.txt".into(),
+            data: translation.text.into_bytes(),
+        }],
+    }
+}
+
+fn footer(
+    detected: Option<&str>,
+    source: Option<&str>,
+    target: &str,
+    languages: &Languages,
+    notice: Option<&String>,
+) -> String {
+    let name = |list: &[Language], code: &str| {
+        list.iter()
+            .find(|language| language.code.eq_ignore_ascii_case(code))
+            .map_or_else(|| code.to_string(), |language| language.name.clone())
+    };
+    let from = match (source, detected) {
+        (Some(source), _) => format!("from {} ", name(&languages.source, source)),
+        (None, Some(detected)) => format!("from {} (detected) ", name(&languages.source, detected)),
+        (None, None) => String::new(),
+    };
+    let to = name(&languages.target, target);
+    let mut footer = format!("_Translated {from}to {to}._");
+    if let Some(notice) = notice {
+        footer += &format!("\n\n_{notice}_");
+    }
+    footer
+}
+
+fn unrecognized_language(kind: &str, lang: &str, supported: &[Language]) -> String {
+    let codes: Vec<_> = supported
+        .iter()
+        .map(|language| language.code.as_str())
+        .collect();
+    format!(
+        "Unrecognized {kind} language {lang}, supported languages are: {}.",
+        codes.join(", "),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_document, parse_options, translation_reply};
+    use crate::translator::{Formality, Language, Languages, Options, Translation};
+    use crate::transport::{Attachment, Reply};
+
+    #[test]
+    fn documents() {
+        assert!(is_document("notes.txt"));
+        assert!(is_document("page.HTML"));
+        assert!(!is_document("photo.png"));
+        assert!(!is_document("README"));
+    }
+
+    #[test]
+    fn options() {
+        let (options, text) =
+            parse_options(r#"formality=less context="Rust code" Hello there"#).unwrap();
+        assert_eq!(
+            options,
+            Options {
+                formality: Some(Formality::Less),
+                context: Some("Rust code".into()),
+                ..Options::default()
+            },
+        );
+        assert_eq!(text.trim(), "Hello there");
+        let (options, text) = parse_options("1+1=2").unwrap();
+        assert_eq!(options, Options::default());
+        assert_eq!(text, "1+1=2");
+        assert!(parse_options("formality=very Hi").is_err());
+    }
+
+    #[test]
+    fn reply() {
+        let language = |code: &str, name: &str| Language {
+            code: code.into(),
+            name: name.into(),
+        };
+        let languages = Languages {
+            source: vec![language("DE", "German")],
+            target: vec![language("EN-US", "English (American)")],
+        };
+        let translation = || Translation {
+            text: "Hello".into(),
+            detected_source: Some("DE".into()),
+            notice: None,
+        };
+        assert_eq!(
+            translation_reply(translation(), None, "EN-US", &languages),
+            Reply::text("Hello\n\n_Translated from German (detected) to English (American)._"),
+        );
+        assert_eq!(
+            translation_reply(translation(), Some("de"), "PL", &languages),
+            Reply::text("Hello\n\n_Translated from German to PL._"),
+        );
+        let long = Translation {
+            text: "Hello ".repeat(400),
+            ..translation()
+        };
+        assert_eq!(
+            translation_reply(long, Some("DE"), "PL", &languages),
+            Reply {
+                content: "_Translated from German to PL._".into(),
+                attachments: vec![Attachment {
+                    filename: "translation.txt".into(),
+                    data: "Hello ".repeat(400).into_bytes(),
+                }],
+            },
+        );
+    }
+}
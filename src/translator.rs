@@ -0,0 +1,163 @@
+// SPDX-FileCopyrightText: 2023 Konrad Borowski <konrad@borowski.pw>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::deepl::DeepL;
+use crate::libretranslate::LibreTranslate;
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use poise::ChoiceParameter;
+
+pub struct Translation {
+    pub text: String,
+    /// Source language guessed by the service when none was provided.
+    pub detected_source: Option<String>,
+    /// Remark shown below the translation, like a quota warning.
+    pub notice: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Language {
+    pub code: String,
+    /// Human-readable name, like `German`.
+    pub name: String,
+}
+
+/// Languages supported by a translation service.
+#[derive(Clone, Debug, Default)]
+pub struct Languages {
+    pub source: Vec<Language>,
+    pub target: Vec<Language>,
+}
+
+/// Finds `code` in `list` ignoring case, returning the code as spelled by the service.
+pub fn find_language<'a>(list: &'a [Language], code: &str) -> Option<&'a str> {
+    list.iter()
+        .find(|language| language.code.eq_ignore_ascii_case(code))
+        .map(|language| language.code.as_str())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ChoiceParameter)]
+pub enum Formality {
+    #[name = "default"]
+    Default,
+    #[name = "more"]
+    More,
+    #[name = "less"]
+    Less,
+    #[name = "prefer_more"]
+    PreferMore,
+    #[name = "prefer_less"]
+    PreferLess,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ChoiceParameter)]
+pub enum TagHandling {
+    #[name = "xml"]
+    Xml,
+    #[name = "html"]
+    Html,
+}
+
+/// Optional translation settings, services reject the ones they don't support.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Options {
+    pub formality: Option<Formality>,
+    pub glossary_id: Option<String>,
+    pub preserve_formatting: bool,
+    pub tag_handling: Option<TagHandling>,
+    /// Text surrounding the translated text that influences the translation.
+    pub context: Option<String>,
+}
+
+#[async_trait]
+pub trait Translator: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Target language used when none is provided.
+    fn default_target(&self) -> &'static str;
+
+    async fn languages(&self) -> Result<Languages>;
+
+    /// Translates `text`, guessing the source language when it's `None`.
+    async fn translate(
+        &self,
+        text: &str,
+        source: Option<&str>,
+        target: &str,
+        options: &Options,
+    ) -> Result<Translation>;
+
+    /// Translates a text file, the translation contains the translated file.
+    async fn translate_document(
+        &self,
+        _name: &str,
+        _contents: String,
+        _source: Option<&str>,
+        _target: &str,
+        _options: &Options,
+    ) -> Result<Translation> {
+        bail!("{} cannot translate documents.", self.name())
+    }
+}
+
+/// Configured translation services.
+pub struct Translators {
+    pub deepl: Option<DeepL>,
+    pub libretranslate: Option<LibreTranslate>,
+}
+
+impl Translators {
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Translator> {
+        [
+            self.deepl.as_ref().map(|t| t as &dyn Translator),
+            self.libretranslate.as_ref().map(|t| t as &dyn Translator),
+        ]
+        .into_iter()
+        .flatten()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    pub fn deepl(&self) -> Result<&DeepL> {
+        self.deepl
+            .as_ref()
+            .ok_or_else(|| anyhow!("DeepL is not configured."))
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.iter().map(|t| t.name())
+    }
+
+    /// Returns translator called `name`, or the default one when `name` is `None`.
+    pub fn get(&self, name: Option<&str>) -> Option<&dyn Translator> {
+        match name {
+            None => self.iter().next(),
+            Some(name) => self.iter().find(|t| t.name().eq_ignore_ascii_case(name)),
+        }
+    }
+
+    /// Returns translator saved for a server, or the default one when none
+    /// was saved or the saved one is no longer configured.
+    pub fn for_guild(&self, saved: Option<&str>) -> Option<&dyn Translator> {
+        saved
+            .and_then(|name| self.get(Some(name)))
+            .or_else(|| self.get(None))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Data;
+
+    #[test]
+    fn saved_translator() {
+        let translators = Data::default().translators;
+        let name = |saved| translators.for_guild(saved).map(|t| t.name());
+        assert_eq!(name(Some("deepl")), Some("deepl"));
+        assert_eq!(name(Some("libretranslate")), Some("deepl"));
+        assert_eq!(name(None), Some("deepl"));
+    }
+}
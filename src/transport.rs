@@ -0,0 +1,135 @@
+// SPDX-FileCopyrightText: 2023 Konrad Borowski <konrad@borowski.pw>
+//
+// SPDX-License-Identifier: AGPL-3.0-or-later
+
+use crate::ratelimit::Origin;
+use crate::{Context, Data};
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use poise::{CreateReply, ReplyHandle};
+use serenity::builder::CreateMessage;
+use serenity::model::channel::AttachmentType;
+
+const MAX_ATTACHMENT_SIZE: u64 = 100_000;
+
+/// Message posted in response to a command.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Reply {
+    pub content: String,
+    pub attachments: Vec<Attachment>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Attachment {
+    pub filename: String,
+    pub data: Vec<u8>,
+}
+
+impl Reply {
+    pub fn text(content: impl Into<String>) -> Self {
+        Self {
+            content: content.into(),
+            attachments: Vec::new(),
+        }
+    }
+
+    fn build<'a>(self, m: &'a mut CreateReply<'static>) -> &'a mut CreateReply<'static> {
+        for Attachment { filename, data } in self.attachments {
+            m.attachment(AttachmentType::Bytes {
+                data: data.into(),
+                filename,
+            });
+        }
+        m.content(self.content)
+    }
+
+    /// Builds a message sent outside of a command invocation.
+    pub fn build_message<'a, 'b>(self, m: &'b mut CreateMessage<'a>) -> &'b mut CreateMessage<'a> {
+        for Attachment { filename, data } in self.attachments {
+            m.add_file(AttachmentType::Bytes {
+                data: data.into(),
+                filename,
+            });
+        }
+        m.content(self.content)
+    }
+}
+
+/// Where commands read their input files from and post their responses to.
+///
+/// Implemented by poise contexts, abstracted away so that command logic
+/// can be tested without connecting to Discord.
+#[async_trait]
+pub trait Transport: Sync {
+    type Message: Send + Sync;
+
+    fn data(&self) -> &Data;
+
+    fn origin(&self) -> Origin;
+
+    async fn send(&self, reply: Reply) -> Result<Self::Message>;
+
+    async fn edit(&self, message: &Self::Message, reply: Reply) -> Result<()>;
+
+    /// Text files attached to the message that invoked the command,
+    /// attachments with names not accepted by `accept` are skipped.
+    // The lifetime is explicit because async_trait mishandles elided
+    // lifetimes in function pointers.
+    async fn attached_files(
+        &self,
+        accept: for<'n> fn(&'n str) -> bool,
+    ) -> Result<Vec<(String, String)>>;
+
+    async fn say(&self, content: String) -> Result<Self::Message> {
+        self.send(Reply::text(content)).await
+    }
+}
+
+#[async_trait]
+impl<'a> Transport for Context<'a> {
+    type Message = ReplyHandle<'a>;
+
+    fn data(&self) -> &Data {
+        poise::Context::data(*self)
+    }
+
+    fn origin(&self) -> Origin {
+        Origin {
+            user: self.author().id.0,
+            channel: self.channel_id().0,
+            guild: self.guild_id().map(|id| id.0),
+        }
+    }
+
+    async fn send(&self, reply: Reply) -> Result<ReplyHandle<'a>> {
+        Ok(poise::Context::send(*self, |m| reply.build(m)).await?)
+    }
+
+    async fn edit(&self, message: &ReplyHandle<'a>, reply: Reply) -> Result<()> {
+        message.edit(*self, |m| reply.build(m)).await?;
+        Ok(())
+    }
+
+    async fn attached_files(
+        &self,
+        accept: for<'n> fn(&'n str) -> bool,
+    ) -> Result<Vec<(String, String)>> {
+        let Context::Prefix(ctx) = self else {
+            return Ok(Vec::new());
+        };
+        let mut files = Vec::new();
+        for attachment in &ctx.msg.attachments {
+            let name = &attachment.filename;
+            if !accept(name) {
+                continue;
+            }
+            if attachment.size > MAX_ATTACHMENT_SIZE {
+                bail!("Attachment {name} is too large");
+            }
+            let contents = String::from_utf8(attachment.download().await?)
+                .map_err(|_| anyhow!("Attachment {name} is not a text file"))?;
+            files.push((name.clone(), contents));
+        }
+        Ok(files)
+    }
+}